@@ -0,0 +1,216 @@
+use crate::{
+	find_puz_start, parse_a_puz, read_header, reader::FieldReader, Header, ParseError,
+	ParsePuzError, PuzFile, PuzVersion, PuzzleType, SolutionType, TextEncoding,
+};
+use alloc::borrow::Cow;
+
+/// A string of a puz file, decoded only when needed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PuzStr<'a> {
+	bytes: &'a [u8],
+	encoding: TextEncoding,
+}
+
+impl<'a> PuzStr<'a> {
+	/// The encoded bytes, without the NUL terminator
+	pub fn as_bytes(&self) -> &'a [u8] {
+		self.bytes
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.bytes.is_empty()
+	}
+
+	/// Decodes the string. Only Windows-1252 strings outside of ASCII are
+	/// copied.
+	pub fn decode(&self) -> Result<Cow<'a, str>, ParsePuzError> {
+		match self.encoding {
+			TextEncoding::Windows1252 if !self.bytes.is_ascii() => {
+				self.encoding.decode(self.bytes).map(Cow::Owned)
+			}
+			_ => Ok(Cow::Borrowed(core::str::from_utf8(self.bytes)?)),
+		}
+	}
+}
+
+/// A view of a puz file that borrows the grids and strings from the parsed
+/// bytes instead of copying them.
+///
+/// Only the header, grids and strings are read, the checksums and extension
+/// sections are checked by `into_owned`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PuzFileRef<'a> {
+	/// All of the parsed bytes
+	bytes: &'a [u8],
+
+	pub preamble: &'a [u8],
+
+	pub version: PuzVersion,
+
+	pub width: u8,
+
+	pub height: u8,
+
+	pub clue_count: u16,
+
+	pub puzzle_type: PuzzleType,
+
+	pub solution_type: SolutionType,
+
+	/// The raw solution board, see `Grid::from_bytes`
+	pub solution: &'a [u8],
+
+	/// The raw board as filled in by the player
+	pub player_state: &'a [u8],
+
+	pub title: PuzStr<'a>,
+
+	pub author: PuzStr<'a>,
+
+	pub copyright: PuzStr<'a>,
+
+	/// The NUL-terminated clues
+	clues: &'a [u8],
+
+	pub notes: PuzStr<'a>,
+}
+
+impl<'a> PuzFileRef<'a> {
+	/// Clues in numbering order, across before down for the same number
+	pub fn clues(&self) -> impl Iterator<Item = PuzStr<'a>> + 'a {
+		let encoding = self.version.text_encoding();
+		self.clues
+			.split(|&byte| byte == 0)
+			.take(self.clue_count as usize)
+			.map(move |bytes| PuzStr { bytes, encoding })
+	}
+
+	/// Parses the whole file, see `parse_a_puz`
+	pub fn into_owned(self) -> Result<PuzFile, ParseError> {
+		parse_a_puz(self.bytes)
+	}
+}
+
+/// Parses a puz file into a view that borrows from `puz_bytes`
+pub fn parse_a_puz_ref(puz_bytes: &[u8]) -> Result<PuzFileRef<'_>, ParseError> {
+	let start_offset = find_puz_start(puz_bytes)?;
+
+	let mut reader = FieldReader::new(puz_bytes, start_offset);
+	parse_puz_ref_at(&mut reader, start_offset).map_err(|kind| ParseError {
+		kind,
+		field: reader.field,
+		offset: reader.position,
+		puz_start: Some(start_offset),
+	})
+}
+
+fn parse_puz_ref_at<'a>(
+	reader: &mut FieldReader<'a>,
+	start_offset: usize,
+) -> Result<PuzFileRef<'a>, ParsePuzError> {
+	let Header {
+		version,
+		width,
+		height,
+		clue_count,
+		..
+	} = read_header(reader)?;
+	let puzzle_type = reader.u16("puzzle type")?.try_into()?;
+	let solution_type = reader.u16("solution type")?.try_into()?;
+
+	let board_size = width as usize * height as usize;
+	let solution = reader.bytes("solution", board_size)?;
+	let player_state = reader.bytes("player state", board_size)?;
+
+	let encoding = version.text_encoding();
+	let expected = clue_count as usize + 4;
+	let mut found = 0;
+	let mut string = |reader: &mut FieldReader<'a>, field| {
+		let bytes = reader
+			.string(field)
+			.ok_or(ParsePuzError::StringCountMismatch {
+				clue_count,
+				expected,
+				found,
+			})?;
+		found += 1;
+		Ok::<_, ParsePuzError>(bytes)
+	};
+
+	let title = string(reader, "title")?;
+	let author = string(reader, "author")?;
+	let copyright = string(reader, "copyright")?;
+	let clues_start = reader.position;
+	for _ in 0..clue_count {
+		string(reader, "clue")?;
+	}
+	let clues = &reader.bytes[clues_start..reader.position];
+	let notes = string(reader, "notes")?;
+
+	let text = |bytes| PuzStr { bytes, encoding };
+	Ok(PuzFileRef {
+		bytes: reader.bytes,
+		preamble: &reader.bytes[..start_offset],
+		version,
+		width,
+		height,
+		clue_count,
+		puzzle_type,
+		solution_type,
+		solution,
+		player_state,
+		title: text(title),
+		author: text(author),
+		copyright: text(copyright),
+		clues,
+		notes: text(notes),
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn it_borrows_from_the_bytes() {
+		let fixtures: [&[u8]; 2] = [
+			include_bytes!("../fixtures/test-no-solution.puz"),
+			include_bytes!("../fixtures/test-extensions.puz"),
+		];
+
+		for fixture in fixtures {
+			let expected = parse_a_puz(fixture).expect("Parsing Failed");
+			let puz = parse_a_puz_ref(fixture).expect("Parsing Failed");
+
+			assert_eq!(puz.title.decode().unwrap(), expected.title);
+			let last_clue = puz.clues().last().unwrap();
+			assert!(matches!(last_clue.decode(), Ok(Cow::Borrowed(_))));
+			let clues: Vec<_> = puz.clues().map(|clue| clue.decode().unwrap()).collect();
+			assert_eq!(clues, expected.clues);
+			assert_eq!(puz.notes.decode().unwrap(), expected.notes);
+			assert_eq!(puz.solution, expected.solution.to_bytes());
+			assert_eq!(puz.into_owned().unwrap(), expected);
+		}
+	}
+
+	#[test]
+	fn it_decodes_latin_1_lazily() {
+		let puzzle = include_bytes!("../fixtures/test-extensions.puz");
+
+		let puz = parse_a_puz_ref(puzzle).expect("Parsing Failed");
+		assert_eq!(puz.title.as_bytes(), b"Caf\xe9 cr\xe8me");
+		assert!(matches!(puz.title.decode(), Ok(Cow::Owned(title)) if title == "Café crème"));
+
+		// cut off within the notes
+		let notes_position = puzzle.windows(5).position(|bytes| bytes == b"Notes");
+		let truncated = &puzzle[..(notes_position.unwrap() + 5)];
+		assert!(matches!(
+			parse_a_puz_ref(truncated),
+			Err(ParseError {
+				kind: ParsePuzError::StringCountMismatch { found: 7, .. },
+				field: "notes",
+				..
+			})
+		));
+	}
+}
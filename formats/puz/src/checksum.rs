@@ -0,0 +1,282 @@
+use crate::diagnostics::Diagnostics;
+use crate::{Crc16Checksum, DiagnosticKind, ParsePuzError};
+use alloc::{string::String, vec::Vec};
+use core::fmt;
+
+/// The part of a puz file a checksum covers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumRegion {
+	/// The whole file: board configuration, grids and strings
+	File,
+	/// Width, height, clue count, puzzle type and solution type
+	BoardConfiguration,
+	/// The data of an extension section
+	Section([u8; 4]),
+	/// The masked checksum of the board configuration
+	MaskedBoardConfiguration,
+	/// The masked checksum of the solution grid
+	MaskedSolution,
+	/// The masked checksum of the player state grid
+	MaskedPlayerState,
+	/// The masked checksum of the strings
+	MaskedStrings,
+}
+
+impl fmt::Display for ChecksumRegion {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::File => write!(f, "file"),
+			Self::BoardConfiguration => write!(f, "board configuration"),
+			Self::Section(name) => write!(f, "{} section", String::from_utf8_lossy(name)),
+			Self::MaskedBoardConfiguration => write!(f, "masked board configuration"),
+			Self::MaskedSolution => write!(f, "masked solution"),
+			Self::MaskedPlayerState => write!(f, "masked player state"),
+			Self::MaskedStrings => write!(f, "masked strings"),
+		}
+	}
+}
+
+impl Crc16Checksum {
+	/// Checksum of the given bytes
+	pub fn of(data: &[u8]) -> Self {
+		Self(checksum_region(data, 0))
+	}
+
+	/// Continues the checksum with more bytes. Larger regions of a puz file
+	/// are checksummed by chaining the checksums of their parts.
+	pub fn update(self, data: &[u8]) -> Self {
+		Self(checksum_region(data, self.0))
+	}
+}
+
+/// Key the masked checksums are XORed with
+const MASK: &[u8; 8] = b"ICHEATED";
+
+/// Four checksums stored in the header, masked with the string "ICHEATED".
+/// The low bytes of all four checksums come first, then the high bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MaskedChecksums {
+	pub board_configuration: Crc16Checksum,
+	pub solution: Crc16Checksum,
+	pub player_state: Crc16Checksum,
+	/// Checksum of the strings, see the file checksum
+	pub strings: Crc16Checksum,
+}
+
+impl MaskedChecksums {
+	fn checksums(&self) -> [u16; 4] {
+		[
+			self.board_configuration.0,
+			self.solution.0,
+			self.player_state.0,
+			self.strings.0,
+		]
+	}
+
+	pub fn to_masked_bytes(&self) -> [u8; 8] {
+		let mut bytes = [0; 8];
+		for (index, checksum) in self.checksums().into_iter().enumerate() {
+			let [low, high] = checksum.to_le_bytes();
+			bytes[index] = low ^ MASK[index];
+			bytes[index + 4] = high ^ MASK[index + 4];
+		}
+		bytes
+	}
+
+	pub fn from_masked_bytes(bytes: [u8; 8]) -> Self {
+		let checksum = |index: usize| {
+			Crc16Checksum(u16::from_le_bytes([
+				bytes[index] ^ MASK[index],
+				bytes[index + 4] ^ MASK[index + 4],
+			]))
+		};
+
+		Self {
+			board_configuration: checksum(0),
+			solution: checksum(1),
+			player_state: checksum(2),
+			strings: checksum(3),
+		}
+	}
+
+	/// The stored and the calculated checksum of every masked region, in the
+	/// order they are stored in the file
+	pub(crate) fn compare(
+		&self,
+		actual: &Self,
+	) -> [(ChecksumRegion, Crc16Checksum, Crc16Checksum); 4] {
+		[
+			(
+				ChecksumRegion::MaskedBoardConfiguration,
+				self.board_configuration,
+				actual.board_configuration,
+			),
+			(
+				ChecksumRegion::MaskedSolution,
+				self.solution,
+				actual.solution,
+			),
+			(
+				ChecksumRegion::MaskedPlayerState,
+				self.player_state,
+				actual.player_state,
+			),
+			(ChecksumRegion::MaskedStrings, self.strings, actual.strings),
+		]
+	}
+}
+
+/// The header checksums of a puz file, calculated from its encoded parts
+pub(crate) struct HeaderChecksums {
+	pub file: Crc16Checksum,
+	pub board_configuration: Crc16Checksum,
+	pub masked: MaskedChecksums,
+}
+
+impl HeaderChecksums {
+	pub fn calculate(
+		board_configuration: &[u8],
+		solution: &[u8],
+		player_state: &[u8],
+		strings: &EncodedStrings,
+		include_notes: bool,
+	) -> Self {
+		let board_configuration = Crc16Checksum::of(board_configuration);
+		let file = board_configuration.update(solution).update(player_state);
+		let file = Crc16Checksum(strings.checksum(include_notes, file.0));
+
+		Self {
+			file,
+			board_configuration,
+			masked: MaskedChecksums {
+				board_configuration,
+				solution: Crc16Checksum::of(solution),
+				player_state: Crc16Checksum::of(player_state),
+				strings: Crc16Checksum(strings.checksum(include_notes, 0)),
+			},
+		}
+	}
+}
+
+/// Reports a mismatch between the checksum stored at `offset` and the one
+/// calculated from the data
+pub(crate) fn verify(
+	diagnostics: &mut Diagnostics,
+	offset: usize,
+	region: ChecksumRegion,
+	expected: Crc16Checksum,
+	actual: Crc16Checksum,
+) -> Result<(), ParsePuzError> {
+	if expected == actual {
+		return Ok(());
+	}
+
+	let (expected, actual) = (expected.into(), actual.into());
+	diagnostics.report(
+		offset,
+		DiagnosticKind::ChecksumMismatch {
+			region,
+			expected,
+			actual,
+		},
+		|| ParsePuzError::ChecksumMismatch {
+			region,
+			expected,
+			actual,
+		},
+	)
+}
+
+/// The rotating checksum used throughout the puz format: before adding a
+/// byte, the previous checksum is rotated right by one bit.
+pub(crate) fn checksum_region(data: &[u8], initial: u16) -> u16 {
+	data.iter().fold(initial, |checksum, &byte| {
+		checksum.rotate_right(1).wrapping_add(byte as u16)
+	})
+}
+
+/// The encoded strings of a puz file, as they are used for checksums
+pub(crate) struct EncodedStrings<'a> {
+	pub title: &'a [u8],
+	pub author: &'a [u8],
+	pub copyright: &'a [u8],
+	pub clues: Vec<&'a [u8]>,
+	pub notes: &'a [u8],
+}
+
+impl EncodedStrings<'_> {
+	/// Checksum of the strings. Title, author, copyright and notes count
+	/// including their NUL terminator, clues without. Empty strings are left
+	/// out completely, notes only count from version 1.3 on.
+	pub fn checksum(&self, include_notes: bool, initial: u16) -> u16 {
+		let with_terminator = |checksum, string: &[u8]| {
+			if string.is_empty() {
+				checksum
+			} else {
+				checksum_region(&[0], checksum_region(string, checksum))
+			}
+		};
+
+		let mut checksum = initial;
+		checksum = with_terminator(checksum, self.title);
+		checksum = with_terminator(checksum, self.author);
+		checksum = with_terminator(checksum, self.copyright);
+		for clue in &self.clues {
+			checksum = checksum_region(clue, checksum);
+		}
+		if include_notes {
+			checksum = with_terminator(checksum, self.notes);
+		}
+
+		checksum
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn it_masks_checksums() {
+		let checksums = MaskedChecksums {
+			board_configuration: Crc16Checksum(0x0000),
+			solution: Crc16Checksum(0x1234),
+			player_state: Crc16Checksum(0xffff),
+			strings: Crc16Checksum(0x00ff),
+		};
+
+		let bytes = checksums.to_masked_bytes();
+
+		assert_eq!(
+			bytes,
+			[
+				b'I',
+				b'C' ^ 0x34,
+				b'H' ^ 0xff,
+				b'E' ^ 0xff,
+				b'A',
+				b'T' ^ 0x12,
+				b'E' ^ 0xff,
+				b'D'
+			]
+		);
+		assert_eq!(MaskedChecksums::from_masked_bytes(bytes), checksums);
+	}
+
+	#[test]
+	fn it_chains_checksums() {
+		assert_eq!(
+			Crc16Checksum::of(b"ACROSS").update(b"&DOWN"),
+			Crc16Checksum::of(b"ACROSS&DOWN")
+		);
+	}
+
+	#[test]
+	fn it_rotates_before_adding() {
+		assert_eq!(checksum_region(b"", 0x1234), 0x1234);
+		assert_eq!(checksum_region(&[1], 0x0001), 0x8001);
+		assert_eq!(checksum_region(&[1, 2], 0), 0x8002);
+		assert_eq!(checksum_region(&[0xff], 0xffff), 0x00fe);
+	}
+}
@@ -0,0 +1,552 @@
+use crate::solve::letter_cell;
+use crate::{Cell, CellFlags, PuzFile, Scope, SolveError, SolveSession};
+use alloc::{borrow::ToOwned, collections::BTreeSet, string::String, vec, vec::Vec};
+
+/// Identifies a player taking part in a co-solve. Every peer needs its own id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PeerId(pub u32);
+
+/// Lamport timestamp of an operation. All peers order operations the same
+/// way: by counter, and concurrent operations by peer id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timestamp {
+	pub counter: u64,
+	pub peer: PeerId,
+}
+
+impl Timestamp {
+	/// The state of the puzzle file every peer starts with
+	const INITIAL: Self = Self {
+		counter: 0,
+		peer: PeerId(0),
+	};
+}
+
+/// What a player entered into a square
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Contents {
+	pub cell: Cell,
+	pub rebus: Option<String>,
+}
+
+/// A change to the shared solve state, to be sent to the other peers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CoOperation {
+	/// New contents of a square, an empty cell for clearing it. The latest
+	/// entry wins.
+	Enter {
+		index: usize,
+		contents: Contents,
+		at: Timestamp,
+	},
+	/// The contents entered at `checked` were found to be wrong
+	Check {
+		index: usize,
+		checked: Timestamp,
+		at: Timestamp,
+	},
+	/// The square shows its solution from now on
+	Reveal {
+		index: usize,
+		replaced_wrong: bool,
+		at: Timestamp,
+	},
+}
+
+impl CoOperation {
+	pub fn at(&self) -> Timestamp {
+		match self {
+			Self::Enter { at, .. } | Self::Check { at, .. } | Self::Reveal { at, .. } => *at,
+		}
+	}
+
+	/// The square the operation changes
+	pub fn index(&self) -> usize {
+		match self {
+			Self::Enter { index, .. } | Self::Check { index, .. } | Self::Reveal { index, .. } => {
+				*index
+			}
+		}
+	}
+}
+
+/// Merged state of a single square
+#[derive(Debug, Clone)]
+struct SquareRegister {
+	contents: Contents,
+	entered: Timestamp,
+	revealed: bool,
+	previously_incorrect: bool,
+	/// The latest entry a check found to be wrong
+	incorrect: Option<Timestamp>,
+}
+
+/// Solve state shared by several players, as a conflict-free replicated data
+/// type.
+///
+/// Local changes return the operations that need to reach the other peers,
+/// in any order and possibly more than once. Peers that received the same
+/// operations have the same puzzle, no matter in which order they arrived:
+/// - the latest entry of a square wins,
+/// - revealed squares stay revealed and show the solution,
+/// - a square is marked incorrect while it holds an entry a check found wrong.
+#[derive(Debug, Clone)]
+pub struct CoSolveSession {
+	peer: PeerId,
+	counter: u64,
+	session: SolveSession,
+	squares: Vec<SquareRegister>,
+	/// Every operation applied so far, to bring new peers up to date
+	operations: Vec<CoOperation>,
+	applied: BTreeSet<Timestamp>,
+	had_user_rebus: bool,
+	had_cell_flags: bool,
+}
+
+impl CoSolveSession {
+	/// Starts co-solving. All peers need to start from the same puzzle.
+	pub fn new(puz: PuzFile, peer: PeerId) -> Self {
+		let squares = (0..puz.player_state.cells.len())
+			.map(|index| {
+				let flags = puz
+					.cell_flags
+					.as_ref()
+					.map(|flags| flags[index])
+					.unwrap_or_default();
+
+				SquareRegister {
+					contents: Contents {
+						cell: puz.player_state.cells[index],
+						rebus: puz
+							.user_rebus
+							.as_ref()
+							.and_then(|entries| entries[index].clone()),
+					},
+					entered: Timestamp::INITIAL,
+					revealed: flags.contains(CellFlags::REVEALED),
+					previously_incorrect: flags.contains(CellFlags::PREVIOUSLY_INCORRECT),
+					incorrect: flags
+						.contains(CellFlags::INCORRECT)
+						.then_some(Timestamp::INITIAL),
+				}
+			})
+			.collect();
+
+		Self {
+			peer,
+			counter: 0,
+			had_user_rebus: puz.user_rebus.is_some(),
+			had_cell_flags: puz.cell_flags.is_some(),
+			session: SolveSession::new(puz),
+			squares,
+			operations: Vec::new(),
+			applied: BTreeSet::new(),
+		}
+	}
+
+	pub fn peer(&self) -> PeerId {
+		self.peer
+	}
+
+	pub fn puz(&self) -> &PuzFile {
+		self.session.puz()
+	}
+
+	pub fn session(&self) -> &SolveSession {
+		&self.session
+	}
+
+	/// All operations applied so far, local and remote
+	pub fn operations(&self) -> &[CoOperation] {
+		&self.operations
+	}
+
+	pub fn is_solved(&self) -> Result<bool, SolveError> {
+		self.session.is_solved()
+	}
+
+	fn next_timestamp(&mut self) -> Timestamp {
+		self.counter += 1;
+		Timestamp {
+			counter: self.counter,
+			peer: self.peer,
+		}
+	}
+
+	/// Applies an operation of another peer. Operations that were applied
+	/// before are ignored, and ones for squares outside of the puzzle are
+	/// rejected.
+	pub fn merge(&mut self, operation: &CoOperation) -> Result<(), SolveError> {
+		if operation.index() >= self.squares.len() {
+			return Err(SolveError::NoSquare(operation.index()));
+		}
+
+		let at = operation.at();
+		self.counter = self.counter.max(at.counter);
+		if !self.applied.insert(at) {
+			return Ok(());
+		}
+
+		let index = match *operation {
+			CoOperation::Enter {
+				index,
+				ref contents,
+				at,
+			} => {
+				let square = &mut self.squares[index];
+				if at > square.entered {
+					square.contents = contents.clone();
+					square.entered = at;
+				}
+				index
+			}
+			CoOperation::Check { index, checked, .. } => {
+				let square = &mut self.squares[index];
+				square.incorrect = square.incorrect.max(Some(checked));
+				index
+			}
+			CoOperation::Reveal {
+				index,
+				replaced_wrong,
+				..
+			} => {
+				let square = &mut self.squares[index];
+				square.revealed = true;
+				square.previously_incorrect |= replaced_wrong;
+				index
+			}
+		};
+
+		self.operations.push(operation.clone());
+		self.update_square(index);
+		Ok(())
+	}
+
+	/// Writes the merged state of a square into the puzzle
+	fn update_square(&mut self, index: usize) {
+		let square = &self.squares[index];
+		let puz = self.session.puz_mut();
+
+		let (cell, rebus) = if square.revealed {
+			let rebus = puz
+				.rebus
+				.as_ref()
+				.and_then(|rebus| rebus.solution_at(index))
+				.map(str::to_owned);
+			(puz.solution.cells[index], rebus)
+		} else {
+			(square.contents.cell, square.contents.rebus.clone())
+		};
+
+		puz.player_state.cells[index] = cell;
+		let size = puz.player_state.cells.len();
+		puz.user_rebus.get_or_insert_with(|| vec![None; size])[index] = rebus;
+
+		let incorrect = !square.revealed && square.incorrect == Some(square.entered);
+		let flags = &mut puz
+			.cell_flags
+			.get_or_insert_with(|| vec![CellFlags::empty(); size])[index];
+		flags.set(CellFlags::REVEALED, square.revealed);
+		flags.set(CellFlags::INCORRECT, incorrect);
+		flags.set(
+			CellFlags::PREVIOUSLY_INCORRECT,
+			square.previously_incorrect || (square.incorrect.is_some() && !incorrect),
+		);
+
+		// Sections are only added when needed, independent of the order
+		// the operations arrived in
+		if !self.had_user_rebus && puz.user_rebus.iter().flatten().all(Option::is_none) {
+			puz.user_rebus = None;
+		}
+		if !self.had_cell_flags && puz.cell_flags.iter().flatten().all(CellFlags::is_empty) {
+			puz.cell_flags = None;
+		}
+
+		self.session.update_numbering();
+	}
+
+	fn local(&mut self, operation: CoOperation) -> CoOperation {
+		self.merge(&operation)
+			.expect("local operations are for squares of the puzzle");
+		operation
+	}
+
+	fn enter(
+		&mut self,
+		scope: Scope,
+		cell: Cell,
+		rebus: Option<&str>,
+	) -> Result<Vec<CoOperation>, SolveError> {
+		let squares: Vec<usize> = self
+			.session
+			.squares(scope)?
+			.into_iter()
+			.filter(|&index| !self.squares[index].revealed)
+			.collect();
+
+		Ok(squares
+			.into_iter()
+			.map(|index| {
+				let at = self.next_timestamp();
+				self.local(CoOperation::Enter {
+					index,
+					contents: Contents {
+						cell,
+						rebus: rebus.map(str::to_owned),
+					},
+					at,
+				})
+			})
+			.collect())
+	}
+
+	/// Enters a letter into a white square. Revealed squares can not be
+	/// changed anymore.
+	pub fn enter_letter(
+		&mut self,
+		index: usize,
+		letter: u8,
+	) -> Result<Vec<CoOperation>, SolveError> {
+		self.session.letter_square(index)?;
+		self.enter(Scope::Square(index), letter_cell(letter)?, None)
+	}
+
+	/// Enters multiple letters into a white square
+	pub fn enter_rebus(
+		&mut self,
+		index: usize,
+		rebus: &str,
+	) -> Result<Vec<CoOperation>, SolveError> {
+		match rebus.bytes().next() {
+			Some(first) => {
+				self.session.letter_square(index)?;
+				self.enter(Scope::Square(index), letter_cell(first)?, Some(rebus))
+			}
+			None => self.clear(Scope::Square(index)),
+		}
+	}
+
+	/// Empties the squares of the scope that are not revealed
+	pub fn clear(&mut self, scope: Scope) -> Result<Vec<CoOperation>, SolveError> {
+		self.enter(scope, Cell::Empty, None)
+	}
+
+	/// Marks the filled, but wrong squares of the scope as incorrect
+	pub fn check(&mut self, scope: Scope) -> Result<Vec<CoOperation>, SolveError> {
+		self.session.require_solution()?;
+
+		let incorrect: Vec<usize> = self
+			.session
+			.squares(scope)?
+			.into_iter()
+			.filter(|&index| self.session.is_filled(index) && !self.session.is_correct(index))
+			.collect();
+
+		Ok(incorrect
+			.into_iter()
+			.map(|index| {
+				let at = self.next_timestamp();
+				self.local(CoOperation::Check {
+					index,
+					checked: self.squares[index].entered,
+					at,
+				})
+			})
+			.collect())
+	}
+
+	/// Reveals the squares of the scope that are not correct yet
+	pub fn reveal(&mut self, scope: Scope) -> Result<Vec<CoOperation>, SolveError> {
+		self.session.require_solution()?;
+
+		let wrong: Vec<usize> = self
+			.session
+			.squares(scope)?
+			.into_iter()
+			.filter(|&index| !self.session.is_correct(index))
+			.collect();
+
+		Ok(wrong
+			.into_iter()
+			.map(|index| {
+				let at = self.next_timestamp();
+				let replaced_wrong = self.session.is_filled(index);
+				self.local(CoOperation::Reveal {
+					index,
+					replaced_wrong,
+					at,
+				})
+			})
+			.collect())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::solve::test_puzzle;
+	use crate::Direction;
+
+	/// Peers connected by a network that delivers messages late, out of
+	/// order and twice
+	struct SimulatedNetwork {
+		peers: Vec<CoSolveSession>,
+		in_flight: Vec<(usize, CoOperation)>,
+		seed: u64,
+	}
+
+	impl SimulatedNetwork {
+		fn new(peer_count: u32, seed: u64) -> Self {
+			let puz = test_puzzle();
+
+			Self {
+				peers: (0..peer_count)
+					.map(|peer| CoSolveSession::new(puz.clone(), PeerId(peer)))
+					.collect(),
+				in_flight: Vec::new(),
+				seed,
+			}
+		}
+
+		fn random(&mut self, below: usize) -> usize {
+			self.seed = self
+				.seed
+				.wrapping_mul(6364136223846793005)
+				.wrapping_add(1442695040888963407);
+			(self.seed >> 33) as usize % below
+		}
+
+		fn send(&mut self, from: usize, operations: Vec<CoOperation>) {
+			for operation in operations {
+				for to in (0..self.peers.len()).filter(|&to| to != from) {
+					self.in_flight.push((to, operation.clone()));
+					if self.random(3) == 0 {
+						self.in_flight.push((to, operation.clone()));
+					}
+				}
+			}
+		}
+
+		/// Delivers some of the messages in flight
+		fn deliver_some(&mut self) {
+			for _ in 0..self.in_flight.len() / 2 {
+				let message = self.random(self.in_flight.len());
+				let (to, operation) = self.in_flight.swap_remove(message);
+				self.peers[to].merge(&operation).unwrap();
+			}
+		}
+
+		fn deliver_all(&mut self) {
+			while !self.in_flight.is_empty() {
+				let message = self.random(self.in_flight.len());
+				let (to, operation) = self.in_flight.swap_remove(message);
+				self.peers[to].merge(&operation).unwrap();
+			}
+		}
+
+		fn assert_converged(&self) {
+			for peer in &self.peers[1..] {
+				assert_eq!(peer.puz(), self.peers[0].puz());
+			}
+		}
+	}
+
+	#[test]
+	fn it_merges_concurrent_entries() {
+		for seed in 0..20 {
+			let mut network = SimulatedNetwork::new(3, seed);
+
+			let operations = network.peers[1].enter_letter(8, b'X').unwrap();
+			network.send(1, operations);
+			let operations = network.peers[2].enter_letter(8, b'Y').unwrap();
+			network.send(2, operations);
+			let operations = network.peers[0].enter_rebus(2, "NO").unwrap();
+			network.send(0, operations);
+			network.deliver_some();
+
+			let operations = network.peers[0].enter_letter(5, b'O').unwrap();
+			network.send(0, operations);
+			network.deliver_all();
+
+			network.assert_converged();
+			let puz = network.peers[0].puz();
+			// Concurrent entries are ordered by peer id
+			assert_eq!(puz.player_state.cells[8], Cell::Letter(b'Y'));
+			assert_eq!(puz.player_state.cells[5], Cell::Letter(b'O'));
+			assert_eq!(puz.user_rebus.as_ref().unwrap()[2].as_deref(), Some("NO"));
+		}
+	}
+
+	#[test]
+	fn it_merges_checks_and_reveals() {
+		for seed in 0..20 {
+			let mut network = SimulatedNetwork::new(3, seed);
+
+			// SUN    SU-
+			// E.O    N.-
+			// ART    AT-
+			let operations = network.peers[0].check(Scope::Puzzle).unwrap();
+			assert_eq!(operations.len(), 2);
+			network.send(0, operations);
+			let operations = network.peers[1].enter_letter(7, b'R').unwrap();
+			network.send(1, operations);
+			let operations = network.peers[2]
+				.reveal(Scope::Word(2, Direction::Down))
+				.unwrap();
+			network.send(2, operations);
+			let operations = network.peers[1].enter_letter(8, b'Q').unwrap();
+			network.send(1, operations);
+			network.deliver_all();
+
+			network.assert_converged();
+			let puz = network.peers[0].puz();
+			let flags = puz.cell_flags.as_ref().unwrap();
+
+			// The wrong N is still there, the checked T was replaced
+			assert_eq!(flags[3], CellFlags::INCORRECT);
+			assert_eq!(puz.player_state.cells[7], Cell::Letter(b'R'));
+			assert_eq!(flags[7], CellFlags::PREVIOUSLY_INCORRECT);
+
+			// Revealed squares keep the solution
+			assert_eq!(puz.player_state.to_bytes(), b"SUNN.OART");
+			assert_eq!(puz.user_rebus.as_ref().unwrap()[8].as_deref(), Some("TEA"));
+			assert!(flags[8].contains(CellFlags::REVEALED));
+		}
+	}
+
+	#[test]
+	fn it_brings_late_peers_up_to_date() {
+		let mut network = SimulatedNetwork::new(2, 7);
+		let operations = network.peers[0].reveal(Scope::Puzzle).unwrap();
+		network.send(0, operations);
+		network.deliver_all();
+
+		let mut late = CoSolveSession::new(test_puzzle(), PeerId(2));
+		for operation in network.peers[1].operations() {
+			late.merge(operation).unwrap();
+		}
+
+		assert_eq!(late.puz(), network.peers[0].puz());
+
+		// Operations from broken peers can not crash the others
+		let broken = CoOperation::Check {
+			index: 99,
+			checked: Timestamp {
+				counter: 1000,
+				peer: PeerId(3),
+			},
+			at: Timestamp {
+				counter: 1000,
+				peer: PeerId(3),
+			},
+		};
+		let operation_count = late.operations().len();
+		assert_eq!(late.merge(&broken), Err(SolveError::NoSquare(99)));
+		assert_eq!(late.operations().len(), operation_count);
+		assert_eq!(late.is_solved(), Ok(true));
+
+		// Revealed squares can not be changed anymore
+		let operations = late.enter_letter(7, b'X').unwrap();
+		assert!(operations.is_empty());
+	}
+}
@@ -0,0 +1,133 @@
+use crate::{ChecksumRegion, ParsePuzError};
+use alloc::{string::String, vec::Vec};
+use thiserror::Error;
+
+/// How the parser deals with problems in a puz file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ParseMode {
+	/// Every problem is an error, for validating files
+	Strict,
+	/// Broken files are errors. Optional data that can not be read is kept
+	/// as-is and reported.
+	#[default]
+	Normal,
+	/// Recovers what it can from broken files and reports the problems
+	Lenient,
+}
+
+/// How much a problem affects the parsed puzzle
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+	/// Optional data could not be read and was kept as-is
+	Warning,
+	/// The file is broken. Only lenient parsing recovers from this, and the
+	/// puzzle may be incomplete or not written back the same way.
+	Error,
+}
+
+/// A problem with a puz file that did not prevent it from being parsed
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("{kind} (at byte 0x{offset:x})")]
+pub struct Diagnostic {
+	/// Position of the problem in the parsed bytes, including the preamble
+	pub offset: usize,
+	pub kind: DiagnosticKind,
+}
+
+impl Diagnostic {
+	pub fn severity(&self) -> Severity {
+		self.kind.severity()
+	}
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum DiagnosticKind {
+	#[error("the timer section (LTIM) could not be read and was ignored: '{0}'")]
+	MalformedTimer(String),
+	#[error("the annotations section (ANNO) could not be read and was ignored")]
+	MalformedAnnotations,
+	#[error(
+		"the {region} checksum is 0x{expected:04x}, but the data has the checksum 0x{actual:04x}"
+	)]
+	ChecksumMismatch {
+		region: ChecksumRegion,
+		expected: u16,
+		actual: u16,
+	},
+	#[error("unknown puzzle type 0x{0:04x}, assumed a normal puzzle")]
+	UnknownPuzzleType(u16),
+	#[error("unknown solution type 0x{0:04x}, assumed a normal solution")]
+	UnknownSolutionType(u16),
+	#[error("expected {expected} strings, but only found {found}, the rest were left empty")]
+	MissingStrings { expected: usize, found: usize },
+	#[error("a string is not valid UTF-8, the invalid parts were replaced")]
+	InvalidUtf8,
+	#[error(
+		"extension section {} is cut off or not terminated and was kept as trailing data",
+		String::from_utf8_lossy(.0)
+	)]
+	BrokenSection([u8; 4]),
+	#[error(
+		"extension section {} could not be read and was kept as-is: {reason}",
+		String::from_utf8_lossy(.name)
+	)]
+	UnreadableSection { name: [u8; 4], reason: String },
+}
+
+impl DiagnosticKind {
+	pub fn severity(&self) -> Severity {
+		match self {
+			Self::MalformedTimer(_)
+			| Self::MalformedAnnotations
+			| Self::BrokenSection(_)
+			| Self::UnreadableSection { .. } => Severity::Warning,
+			_ => Severity::Error,
+		}
+	}
+}
+
+/// Collects the diagnostics of a parse, or turns them into errors depending
+/// on the mode
+pub(crate) struct Diagnostics {
+	pub mode: ParseMode,
+	pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+	pub fn new(mode: ParseMode) -> Self {
+		Self {
+			mode,
+			diagnostics: Vec::new(),
+		}
+	}
+
+	/// Reports a problem the parser can work around. Fails with `error` for
+	/// errors outside of lenient mode, and with the diagnostic in strict mode.
+	pub fn report(
+		&mut self,
+		offset: usize,
+		kind: DiagnosticKind,
+		error: impl FnOnce() -> ParsePuzError,
+	) -> Result<(), ParsePuzError> {
+		let diagnostic = Diagnostic { offset, kind };
+
+		match (self.mode, diagnostic.severity()) {
+			(ParseMode::Lenient, _) | (ParseMode::Normal, Severity::Warning) => {
+				self.diagnostics.push(diagnostic);
+				Ok(())
+			}
+			(ParseMode::Normal, Severity::Error) | (ParseMode::Strict, Severity::Error) => {
+				Err(error())
+			}
+			(ParseMode::Strict, Severity::Warning) => Err(ParsePuzError::Rejected(diagnostic)),
+		}
+	}
+
+	/// Reports a warning, which is only an error in strict mode
+	pub fn warn(&mut self, offset: usize, kind: DiagnosticKind) -> Result<(), ParsePuzError> {
+		debug_assert_eq!(kind.severity(), Severity::Warning);
+		self.report(offset, kind, || {
+			unreachable!("warnings fail with the diagnostic")
+		})
+	}
+}
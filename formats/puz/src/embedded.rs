@@ -0,0 +1,154 @@
+use crate::extensions::is_section_header;
+use crate::reader::FieldReader;
+use crate::{get_puz_start_offset, parse_a_puz, read_header, Header, ParseError, PuzFile};
+
+/// A puzzle found in a larger blob of bytes
+#[derive(Debug, PartialEq)]
+pub struct EmbeddedPuz {
+	/// Position of the puzzle in the blob
+	pub offset: usize,
+	/// Length of the puzzle, up to the end of the last extension section
+	pub length: usize,
+	pub puz: PuzFile,
+}
+
+/// Iterator over the puzzles in a blob, see `find_puzzles`
+#[derive(Debug, Clone)]
+pub struct EmbeddedPuzzles<'a> {
+	bytes: &'a [u8],
+	position: usize,
+}
+
+/// Finds every puzzle in a blob of bytes, e.g. concatenated files or an
+/// archive with puzzles stored as-is.
+///
+/// The declared sizes of a puzzle are used to skip to the next one, so magic
+/// in its strings or sections is not mistaken for another puzzle. Magic that
+/// does not start a valid puzzle yields an error with its position in the
+/// blob, and the search continues right after it.
+pub fn find_puzzles(bytes: &[u8]) -> EmbeddedPuzzles<'_> {
+	EmbeddedPuzzles { bytes, position: 0 }
+}
+
+impl Iterator for EmbeddedPuzzles<'_> {
+	type Item = Result<EmbeddedPuz, ParseError>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		let rest = &self.bytes[self.position..];
+		let offset = self.position + get_puz_start_offset(rest).ok()?;
+		let candidate = &self.bytes[offset..];
+
+		let puz = puz_length(candidate).and_then(|length| {
+			parse_a_puz(&candidate[..length]).map(|puz| EmbeddedPuz {
+				offset,
+				length,
+				puz,
+			})
+		});
+
+		Some(match puz {
+			Ok(puz) => {
+				self.position = offset + puz.length;
+				Ok(puz)
+			}
+			Err(error) => {
+				self.position = offset + 1;
+				Err(ParseError {
+					offset: offset + error.offset,
+					puz_start: Some(offset),
+					..error
+				})
+			}
+		})
+	}
+}
+
+/// Length of the puzzle at the start of `bytes` as declared by its header,
+/// up to the end of the last extension section. A cut off header or grid is
+/// reported at the field it ends in, cut off strings are left to the parser
+/// to report. The puzzle ends before a section that does not fit.
+fn puz_length(bytes: &[u8]) -> Result<usize, ParseError> {
+	let mut reader = FieldReader::new(bytes, 0);
+	let mut length = || {
+		let Header {
+			width,
+			height,
+			clue_count,
+			..
+		} = read_header(&mut reader)?;
+		reader.bytes("puzzle and solution type", 4)?;
+
+		let board_size = width as usize * height as usize;
+		reader.bytes("solution", board_size)?;
+		reader.bytes("player state", board_size)?;
+
+		for _ in 0..(clue_count as usize + 4) {
+			if reader.string("strings").is_none() {
+				return Ok(bytes.len());
+			}
+		}
+
+		// Text following the puzzle can look like a section header, so only
+		// sections that fit into the bytes with their NUL byte are counted
+		while is_section_header(reader.rest()) {
+			let section = reader.rest();
+			let data_length = u16::from_le_bytes([section[4], section[5]]) as usize;
+			if section.get(8 + data_length) != Some(&0) {
+				break;
+			}
+			reader.position += 8 + data_length + 1;
+		}
+		Ok(reader.position)
+	};
+
+	length().map_err(|kind| ParseError {
+		kind,
+		field: reader.field,
+		offset: reader.position,
+		puz_start: Some(0),
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{ParsePuzError, FILE_MAGIC};
+
+	#[test]
+	fn it_finds_embedded_puzzles() {
+		let first = include_bytes!("../fixtures/test-extensions.puz");
+		let second = include_bytes!("../fixtures/test-no-solution.puz");
+
+		let mut blob = b"From: archive\n".to_vec();
+		blob.extend(first);
+		let second_offset = blob.len();
+		blob.extend(second);
+		blob.extend(b"From sender@example.com Sat Oct 17 12:00:00 2026\n");
+		blob.extend(b"\nsee you next week!\n");
+		let broken_offset = blob.len();
+		blob.extend(b"CK");
+		blob.extend(FILE_MAGIC);
+		blob.extend(b"1.3\0");
+
+		let mut puzzles = find_puzzles(&blob);
+
+		// The preamble of the first fixture is not part of the puzzle
+		let found = puzzles.next().unwrap().expect("Parsing Failed");
+		assert_eq!(found.offset, 14 + 10);
+		assert_eq!(found.length, first.len() - 10);
+		assert_eq!(found.puz, parse_a_puz(&first[10..]).unwrap());
+
+		let found = puzzles.next().unwrap().expect("Parsing Failed");
+		assert_eq!(found.offset, second_offset);
+		assert_eq!(found.length, second.len());
+		assert_eq!(found.puz, parse_a_puz(second).unwrap());
+
+		let error = puzzles.next().unwrap().unwrap_err();
+		assert!(matches!(error.kind, ParsePuzError::UnexpectedEnd { .. }));
+		assert_eq!(error.field, "masked checksums");
+		assert_eq!(error.offset, broken_offset + 0x10);
+		assert_eq!(error.relative_offset(), Some(0x10));
+
+		assert!(puzzles.next().is_none());
+	}
+}
@@ -0,0 +1,113 @@
+use crate::ParsePuzError;
+use alloc::string::String;
+use core::fmt::{self, Write};
+
+/// Bytes per line of a hex dump
+const HEX_DUMP_WIDTH: usize = 16;
+
+/// A parse error with the position and the field it happened at
+#[derive(Debug)]
+pub struct ParseError {
+	pub kind: ParsePuzError,
+	/// Name of the field being read
+	pub field: &'static str,
+	/// Position in the parsed bytes, including the preamble
+	pub offset: usize,
+	/// Where the puzzle starts, after the preamble, if it was found
+	pub puz_start: Option<usize>,
+}
+
+impl ParseError {
+	/// The position relative to the start of the puzzle
+	pub fn relative_offset(&self) -> Option<usize> {
+		self.puz_start
+			.map(|puz_start| self.offset.saturating_sub(puz_start))
+	}
+
+	/// Renders the bytes around the error, see `hex_dump`
+	pub fn hex_dump(&self, bytes: &[u8]) -> String {
+		hex_dump(bytes, self.offset, 2)
+	}
+}
+
+impl fmt::Display for ParseError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"{} ({} at byte 0x{:x}",
+			self.kind, self.field, self.offset
+		)?;
+		if let Some(offset) = self.relative_offset() {
+			write!(f, ", 0x{offset:x} into the puzzle")?;
+		}
+		write!(f, ")")
+	}
+}
+
+impl core::error::Error for ParseError {}
+
+/// Renders the lines of a hex dump around `offset`, with `context` lines
+/// before and after, and marks the byte at `offset`:
+///
+/// ```text
+/// 00000020  00 00 00 00 00 00 00 00 00 00 00 00 03 03 04 00  |................|
+///                                               ^^
+/// ```
+pub fn hex_dump(bytes: &[u8], offset: usize, context: usize) -> String {
+	let line = offset / HEX_DUMP_WIDTH;
+	let first_line = line.saturating_sub(context);
+	let last_line = (line + context).min(bytes.len().saturating_sub(1) / HEX_DUMP_WIDTH);
+
+	let mut dump = String::new();
+	for current in first_line..=last_line.max(line) {
+		let start = current * HEX_DUMP_WIDTH;
+		let chunk = bytes.get(start..).unwrap_or_default();
+		let chunk = &chunk[..chunk.len().min(HEX_DUMP_WIDTH)];
+
+		write!(dump, "{start:08x} ").unwrap();
+		for column in 0..HEX_DUMP_WIDTH {
+			match chunk.get(column) {
+				Some(byte) => write!(dump, " {byte:02x}").unwrap(),
+				None => dump.push_str("   "),
+			}
+		}
+		let text: String = chunk
+			.iter()
+			.map(|&byte| match byte {
+				0x20..=0x7e => byte as char,
+				_ => '.',
+			})
+			.collect();
+		writeln!(dump, "  |{text}|").unwrap();
+
+		if current == line {
+			let column = offset % HEX_DUMP_WIDTH;
+			writeln!(dump, "{}^^", " ".repeat(10 + 3 * column)).unwrap();
+		}
+	}
+
+	dump
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn it_renders_hex_dumps() {
+		let bytes: Vec<u8> = (0x30..0x58).collect();
+
+		assert_eq!(
+			hex_dump(&bytes, 0x12, 1),
+			"\
+00000000  30 31 32 33 34 35 36 37 38 39 3a 3b 3c 3d 3e 3f  |0123456789:;<=>?|
+00000010  40 41 42 43 44 45 46 47 48 49 4a 4b 4c 4d 4e 4f  |@ABCDEFGHIJKLMNO|
+                ^^
+00000020  50 51 52 53 54 55 56 57                          |PQRSTUVW|
+"
+		);
+
+		// The end of the data can be marked as well
+		assert!(hex_dump(&bytes, 0x28, 0).ends_with(&format!("{}^^\n", " ".repeat(34))));
+	}
+}
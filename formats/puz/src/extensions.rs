@@ -0,0 +1,479 @@
+use crate::reader::FieldReader;
+use crate::text::split_strings;
+use crate::{Crc16Checksum, ParsePuzError, TextEncoding, WritePuzError, FILE_MAGIC};
+use alloc::{borrow::ToOwned, string::String, vec, vec::Vec};
+
+/// Names of the extension sections this crate understands
+pub(crate) const KNOWN_SECTIONS: [&[u8; 4]; 6] =
+	[b"GRBS", b"RTBL", b"LTIM", b"GEXT", b"RUSR", b"ANNO"];
+
+/// Version of the ANNO section format
+const ANNOTATIONS_VERSION: u8 = 1;
+
+/// An extension section as found after the strings of a puz file
+#[derive(Debug)]
+pub(crate) struct RawSection<'a> {
+	pub name: [u8; 4],
+	/// Position of the section in the bytes it was split from
+	pub offset: usize,
+	pub checksum: u16,
+	pub data: &'a [u8],
+}
+
+impl RawSection<'_> {
+	pub fn to_unknown(&self) -> UnknownSection {
+		UnknownSection {
+			name: self.name,
+			checksum: self.checksum.into(),
+			data: self.data.to_vec(),
+		}
+	}
+}
+
+/// An extension section that is not understood by this crate (or could not
+/// be read), kept as-is so it can be written back
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UnknownSection {
+	pub name: [u8; 4],
+	/// Checksum of the data as stored in the file, which is written back as
+	/// well. Update it with `Crc16Checksum::of` when changing the data.
+	pub checksum: Crc16Checksum,
+	pub data: Vec<u8>,
+}
+
+/// Whether the bytes start with a section name, length and checksum, and not
+/// with the start of a following puzzle, whose checksum could look like a
+/// section name
+pub(crate) fn is_section_header(bytes: &[u8]) -> bool {
+	bytes.len() >= 8
+		&& bytes[..4].iter().all(u8::is_ascii_alphanumeric)
+		&& bytes[2..8] != FILE_MAGIC[..6]
+}
+
+/// Splits the extension sections off the bytes following the notes.
+/// Every section consists of a 4 byte name, the data length, a checksum of the
+/// data, the data and a NUL byte.
+/// Splitting stops at the first bytes that do not start like a section.
+/// Also returns the bytes after the last complete section, and the error if
+/// reading stopped at a broken section.
+pub(crate) fn split_sections(bytes: &[u8]) -> (Vec<RawSection<'_>>, &[u8], Option<ParsePuzError>) {
+	let mut sections = Vec::new();
+	let mut offset = 0;
+
+	while is_section_header(&bytes[offset..]) {
+		let rest = &bytes[offset..];
+		let name = [rest[0], rest[1], rest[2], rest[3]];
+		let length = u16::from_le_bytes([rest[4], rest[5]]) as usize;
+		let checksum = u16::from_le_bytes([rest[6], rest[7]]);
+
+		let Some(data) = rest.get(8..(8 + length)) else {
+			return (sections, rest, Some(ParsePuzError::TruncatedSection(name)));
+		};
+		if rest.get(8 + length) != Some(&0) {
+			return (
+				sections,
+				rest,
+				Some(ParsePuzError::UnterminatedSection(name)),
+			);
+		}
+
+		sections.push(RawSection {
+			name,
+			offset,
+			checksum,
+			data,
+		});
+		offset += 8 + length + 1;
+	}
+
+	(sections, &bytes[offset..], None)
+}
+
+pub(crate) fn expect_board_length(
+	section: &RawSection,
+	board_size: usize,
+) -> Result<(), ParsePuzError> {
+	if section.data.len() == board_size {
+		Ok(())
+	} else {
+		Err(ParsePuzError::UnexpectedSectionLength {
+			name: section.name,
+			expected: board_size,
+			found: section.data.len(),
+		})
+	}
+}
+
+/// Per-cell flags from the GEXT section.
+/// Bits without a known meaning are preserved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct CellFlags(u8);
+
+impl CellFlags {
+	/// The square was marked incorrect at some point
+	pub const PREVIOUSLY_INCORRECT: Self = Self(0x10);
+	/// The square is currently marked incorrect
+	pub const INCORRECT: Self = Self(0x20);
+	/// The contents of the square were given to the player
+	pub const REVEALED: Self = Self(0x40);
+	/// The square is circled
+	pub const CIRCLED: Self = Self(0x80);
+
+	pub const fn empty() -> Self {
+		Self(0)
+	}
+
+	pub const fn from_bits(bits: u8) -> Self {
+		Self(bits)
+	}
+
+	pub const fn bits(&self) -> u8 {
+		self.0
+	}
+
+	pub const fn is_empty(&self) -> bool {
+		self.0 == 0
+	}
+
+	pub const fn contains(&self, other: Self) -> bool {
+		self.0 & other.0 == other.0
+	}
+
+	pub fn insert(&mut self, other: Self) {
+		self.0 |= other.0;
+	}
+
+	pub fn remove(&mut self, other: Self) {
+		self.0 &= !other.0;
+	}
+
+	pub fn set(&mut self, other: Self, value: bool) {
+		if value {
+			self.insert(other);
+		} else {
+			self.remove(other);
+		}
+	}
+}
+
+impl core::ops::BitOr for CellFlags {
+	type Output = Self;
+
+	fn bitor(self, other: Self) -> Self {
+		Self(self.0 | other.0)
+	}
+}
+
+/// Colour of a highlighted square
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Highlight {
+	pub red: u8,
+	pub green: u8,
+	pub blue: u8,
+}
+
+/// Solver notes on a square that do not fit into the player state. Stored in
+/// the ANNO section, which is private to this crate, so other tools skip it.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Annotation {
+	/// The letter in the player state is only tentative
+	pub pencil: bool,
+	/// Letters the solver considers for the square
+	pub candidates: Vec<u8>,
+	pub highlight: Option<Highlight>,
+}
+
+impl Annotation {
+	const PENCIL: u8 = 0x01;
+	const HIGHLIGHT: u8 = 0x02;
+
+	/// The count of candidates is stored in a single byte
+	pub const MAX_CANDIDATES: usize = u8::MAX as usize;
+
+	pub fn is_empty(&self) -> bool {
+		*self == Self::default()
+	}
+
+	/// Appends the encoding of the annotation: a flag byte (0x01 pencil,
+	/// 0x02 highlighted), the RGB bytes of the highlight if there is one,
+	/// and the number of candidates followed by the candidates
+	pub(crate) fn write(&self, bytes: &mut Vec<u8>) -> Result<(), WritePuzError> {
+		let count = u8::try_from(self.candidates.len())
+			.map_err(|_| WritePuzError::TooManyCandidates(self.candidates.len()))?;
+
+		let mut flags = 0;
+		if self.pencil {
+			flags |= Self::PENCIL;
+		}
+		if self.highlight.is_some() {
+			flags |= Self::HIGHLIGHT;
+		}
+		bytes.push(flags);
+
+		if let Some(highlight) = self.highlight {
+			bytes.extend([highlight.red, highlight.green, highlight.blue]);
+		}
+		bytes.push(count);
+		bytes.extend(&self.candidates);
+		Ok(())
+	}
+
+	/// Reads an annotation written by `write`. Unknown flags are rejected, so
+	/// nothing gets lost by reading and writing again.
+	pub(crate) fn read(reader: &mut FieldReader) -> Option<Self> {
+		let flags = reader.u8("annotation flags").ok()?;
+		if flags & !(Self::PENCIL | Self::HIGHLIGHT) != 0 {
+			return None;
+		}
+
+		let highlight = if flags & Self::HIGHLIGHT != 0 {
+			let [red, green, blue] = reader.array("highlight").ok()?;
+			Some(Highlight { red, green, blue })
+		} else {
+			None
+		};
+
+		let count = reader.u8("candidate count").ok()?;
+		let candidates = reader.bytes("candidates", count as usize).ok()?.to_vec();
+
+		Some(Self {
+			pencil: flags & Self::PENCIL != 0,
+			candidates,
+			highlight,
+		})
+	}
+}
+
+/// Reads the ANNO section: a version byte and the annotation of every square
+pub(crate) fn parse_annotations(data: &[u8], board_size: usize) -> Option<Vec<Annotation>> {
+	let mut reader = FieldReader::new(data, 0);
+	if reader.u8("annotations version").ok()? != ANNOTATIONS_VERSION {
+		return None;
+	}
+
+	let annotations = (0..board_size)
+		.map(|_| Annotation::read(&mut reader))
+		.collect::<Option<Vec<_>>>()?;
+
+	reader.rest().is_empty().then_some(annotations)
+}
+
+pub(crate) fn encode_annotations(annotations: &[Annotation]) -> Result<Vec<u8>, WritePuzError> {
+	let mut data = vec![ANNOTATIONS_VERSION];
+	for annotation in annotations {
+		annotation.write(&mut data)?;
+	}
+	Ok(data)
+}
+
+/// Solving time from the LTIM section
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Timer {
+	pub elapsed_seconds: u32,
+	pub running: bool,
+}
+
+impl Timer {
+	/// Parses the ascii representation "<elapsed seconds>,<stopped>", where
+	/// stopped is 1 if the timer is stopped and 0 if it is running
+	pub(crate) fn from_section(data: &[u8]) -> Option<Self> {
+		let text = core::str::from_utf8(data).ok()?;
+		let (elapsed_seconds, stopped) = text.split_once(',')?;
+
+		Some(Self {
+			elapsed_seconds: elapsed_seconds.trim().parse().ok()?,
+			running: match stopped.trim() {
+				"0" => true,
+				"1" => false,
+				_ => return None,
+			},
+		})
+	}
+}
+
+/// Reads the RUSR section: a NUL-terminated string per cell, containing
+/// the rebus entered by the player or nothing
+pub(crate) fn parse_user_rebus(
+	data: &[u8],
+	board_size: usize,
+	encoding: TextEncoding,
+) -> Result<Vec<Option<String>>, ParsePuzError> {
+	let (strings, consumed) = split_strings(data, board_size).unwrap_or_default();
+
+	if strings.len() != board_size || consumed != data.len() {
+		return Err(ParsePuzError::UserRebusCountMismatch {
+			expected: board_size,
+			found: data.iter().filter(|&&byte| byte == 0).count(),
+		});
+	}
+
+	strings
+		.into_iter()
+		.map(|string| match string {
+			b"" => Ok(None),
+			string => encoding.decode(string).map(Some),
+		})
+		.collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RebusEntry {
+	pub key: u8,
+	pub solution: String,
+}
+
+/// Solutions of rebus squares (multiple letters in one square).
+/// The solution board only contains the first letter of those squares.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Rebus {
+	/// GRBS section: per cell 0 for regular squares, otherwise the key of the
+	/// square's solution in `table` plus 1
+	pub board: Vec<u8>,
+	/// RTBL section: the rebus solutions by key, in file order
+	pub table: Vec<RebusEntry>,
+}
+
+impl Rebus {
+	/// Builds the rebus data from the GRBS and RTBL sections and checks that
+	/// every square refers to an existing solution
+	pub(crate) fn from_sections(
+		board: &[u8],
+		table: &[u8],
+		encoding: TextEncoding,
+	) -> Result<Self, ParsePuzError> {
+		let table = encoding.decode(table)?;
+		let table = table
+			.split(';')
+			.map(str::trim)
+			.filter(|entry| !entry.is_empty())
+			.map(|entry| {
+				entry
+					.split_once(':')
+					.and_then(|(key, solution)| {
+						Some(RebusEntry {
+							key: key.trim().parse().ok()?,
+							solution: solution.to_owned(),
+						})
+					})
+					.ok_or_else(|| ParsePuzError::MalformedRebusTable(entry.to_owned()))
+			})
+			.collect::<Result<Vec<_>, _>>()?;
+
+		let rebus = Self {
+			board: board.to_vec(),
+			table,
+		};
+
+		for (index, &value) in rebus.board.iter().enumerate() {
+			if value != 0 && rebus.solution_at(index).is_none() {
+				return Err(ParsePuzError::UnknownRebusKey {
+					index,
+					key: value - 1,
+				});
+			}
+		}
+
+		Ok(rebus)
+	}
+
+	/// The rebus solution of the square at the given index, if it has one
+	pub fn solution_at(&self, index: usize) -> Option<&str> {
+		let key = self.board.get(index)?.checked_sub(1)?;
+
+		self.table
+			.iter()
+			.find(|entry| entry.key == key)
+			.map(|entry| entry.solution.as_str())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn it_keeps_unknown_flag_bits() {
+		let mut flags = CellFlags::from_bits(0x81);
+
+		assert!(flags.contains(CellFlags::CIRCLED));
+		assert!(!flags.contains(CellFlags::REVEALED));
+
+		flags.remove(CellFlags::CIRCLED);
+		flags.insert(CellFlags::REVEALED | CellFlags::INCORRECT);
+
+		assert_eq!(flags.bits(), 0x61);
+	}
+
+	#[test]
+	fn it_parses_the_timer() {
+		assert_eq!(
+			Timer::from_section(b"42,1"),
+			Some(Timer {
+				elapsed_seconds: 42,
+				running: false
+			})
+		);
+		assert_eq!(
+			Timer::from_section(b"3600,0"),
+			Some(Timer {
+				elapsed_seconds: 3600,
+				running: true
+			})
+		);
+		assert_eq!(Timer::from_section(b"42"), None);
+		assert_eq!(Timer::from_section(b"-3,1"), None);
+	}
+
+	#[test]
+	fn it_parses_user_rebus_entries() {
+		let entries = parse_user_rebus(b"\0AB\0\0", 3, TextEncoding::Windows1252).unwrap();
+
+		assert_eq!(entries, [None, Some("AB".to_owned()), None]);
+		assert!(matches!(
+			parse_user_rebus(b"\0\0", 3, TextEncoding::Windows1252),
+			Err(ParsePuzError::UserRebusCountMismatch {
+				expected: 3,
+				found: 2
+			})
+		));
+	}
+
+	#[test]
+	fn it_parses_the_rebus_table() {
+		let rebus =
+			Rebus::from_sections(&[0, 2, 1, 0], b" 0:STAR; 1:TEA;", TextEncoding::Windows1252)
+				.unwrap();
+
+		assert_eq!(rebus.solution_at(0), None);
+		assert_eq!(rebus.solution_at(1), Some("TEA"));
+		assert_eq!(rebus.solution_at(2), Some("STAR"));
+	}
+
+	#[test]
+	fn it_fails_on_unknown_keys() {
+		let result = Rebus::from_sections(&[0, 3], b" 0:STAR;", TextEncoding::Windows1252);
+
+		assert!(matches!(
+			result,
+			Err(ParsePuzError::UnknownRebusKey { index: 1, key: 2 })
+		));
+	}
+
+	#[test]
+	fn it_fails_on_malformed_tables() {
+		let result = Rebus::from_sections(&[1], b" 0:STAR; x:Y;", TextEncoding::Windows1252);
+
+		assert!(matches!(
+			result,
+			Err(ParsePuzError::MalformedRebusTable(entry)) if entry == "x:Y"
+		));
+	}
+}
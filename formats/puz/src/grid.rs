@@ -0,0 +1,103 @@
+use alloc::vec::Vec;
+
+/// A single square of a puz board, as found in the solution and player grids
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(from = "u8", into = "u8"))]
+pub enum Cell {
+	/// Black square, stored as '.'
+	Block,
+	/// Black square of a diagramless puzzle, stored as ':'
+	DiagramlessBlock,
+	/// White square without content, stored as '-'
+	Empty,
+	/// White square containing a letter.
+	/// The byte is kept as-is, it is usually an uppercase ascii letter.
+	Letter(u8),
+}
+
+impl Cell {
+	pub fn is_block(&self) -> bool {
+		matches!(self, Self::Block | Self::DiagramlessBlock)
+	}
+
+	pub fn letter(&self) -> Option<u8> {
+		match self {
+			Self::Letter(letter) => Some(*letter),
+			_ => None,
+		}
+	}
+}
+
+impl From<u8> for Cell {
+	fn from(value: u8) -> Self {
+		match value {
+			b'.' => Self::Block,
+			b':' => Self::DiagramlessBlock,
+			b'-' => Self::Empty,
+			letter => Self::Letter(letter),
+		}
+	}
+}
+
+impl From<Cell> for u8 {
+	fn from(value: Cell) -> Self {
+		match value {
+			Cell::Block => b'.',
+			Cell::DiagramlessBlock => b':',
+			Cell::Empty => b'-',
+			Cell::Letter(letter) => letter,
+		}
+	}
+}
+
+/// A width×height board of cells, stored row by row
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Grid {
+	pub width: u8,
+	pub height: u8,
+	pub cells: Vec<Cell>,
+}
+
+impl Grid {
+	/// Decodes a grid from its `width * height` raw bytes.
+	/// Panics if the byte count does not match the dimensions.
+	pub fn from_bytes(width: u8, height: u8, bytes: &[u8]) -> Self {
+		assert_eq!(bytes.len(), width as usize * height as usize);
+
+		Self {
+			width,
+			height,
+			cells: bytes.iter().map(|&byte| Cell::from(byte)).collect(),
+		}
+	}
+
+	pub fn to_bytes(&self) -> Vec<u8> {
+		self.cells.iter().map(|&cell| u8::from(cell)).collect()
+	}
+
+	/// Index into `cells` for the given position, if it lies on the board
+	pub fn index(&self, row: u8, col: u8) -> Option<usize> {
+		if row < self.height && col < self.width {
+			Some(row as usize * self.width as usize + col as usize)
+		} else {
+			None
+		}
+	}
+
+	/// (row, column) of the given index into `cells`
+	pub fn position(&self, index: usize) -> (u8, u8) {
+		let width = self.width as usize;
+		((index / width) as u8, (index % width) as u8)
+	}
+
+	pub fn get(&self, row: u8, col: u8) -> Option<Cell> {
+		self.index(row, col).map(|index| self.cells[index])
+	}
+
+	/// Iterates over the rows of the board
+	pub fn rows(&self) -> impl Iterator<Item = &[Cell]> {
+		self.cells.chunks(self.width.max(1) as usize)
+	}
+}
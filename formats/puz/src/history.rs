@@ -0,0 +1,476 @@
+use crate::reader::FieldReader;
+use crate::{Annotation, Cell, CellFlags, ParsePuzError};
+use alloc::{borrow::ToOwned, string::String, vec::Vec};
+use core::time::Duration;
+use thiserror::Error;
+
+/// Identifies serialized histories
+const HISTORY_MAGIC: &[u8; 4] = b"PZHI";
+
+const HISTORY_VERSION: u8 = 1;
+
+/// Letters typed within this time of each other are undone together
+const DEFAULT_COALESCE_WITHIN: Duration = Duration::from_secs(1);
+
+#[derive(Error, Debug)]
+pub enum HistoryError {
+	#[error("this is not a serialized history")]
+	NotAHistory,
+	#[error("unknown history version {0}")]
+	UnknownVersion(u8),
+	#[error("unknown operation kind {0}")]
+	UnknownOperation(u8),
+	#[error("the history changes square {index}, but the board only has {size} squares")]
+	SquareOutOfRange { index: usize, size: usize },
+	#[error("invalid annotation in history")]
+	InvalidAnnotation,
+	#[error("a square has {0} candidates, but at most 255 can be stored")]
+	TooManyCandidates(usize),
+	#[error("a rebus entry is {0} bytes long, but must be shorter than 65535 bytes")]
+	RebusTooLong(usize),
+	#[error("invalid rebus entry in history")]
+	InvalidUtf8(#[from] core::str::Utf8Error),
+	#[error("the history data is cut off")]
+	Truncated,
+}
+
+/// What the player did
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperationKind {
+	Letter,
+	Rebus,
+	Pencil,
+	Check,
+	Reveal,
+	Clear,
+	ToggleBlock,
+	Candidate,
+	Highlight,
+}
+
+impl From<OperationKind> for u8 {
+	fn from(kind: OperationKind) -> Self {
+		match kind {
+			OperationKind::Letter => 0,
+			OperationKind::Rebus => 1,
+			OperationKind::Pencil => 2,
+			OperationKind::Check => 3,
+			OperationKind::Reveal => 4,
+			OperationKind::Clear => 5,
+			OperationKind::ToggleBlock => 6,
+			OperationKind::Candidate => 7,
+			OperationKind::Highlight => 8,
+		}
+	}
+}
+
+impl TryFrom<u8> for OperationKind {
+	type Error = HistoryError;
+
+	fn try_from(value: u8) -> Result<Self, Self::Error> {
+		Ok(match value {
+			0 => OperationKind::Letter,
+			1 => OperationKind::Rebus,
+			2 => OperationKind::Pencil,
+			3 => OperationKind::Check,
+			4 => OperationKind::Reveal,
+			5 => OperationKind::Clear,
+			6 => OperationKind::ToggleBlock,
+			7 => OperationKind::Candidate,
+			8 => OperationKind::Highlight,
+			_ => return Err(HistoryError::UnknownOperation(value)),
+		})
+	}
+}
+
+/// Everything the player can change about a single square
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SquareState {
+	pub cell: Cell,
+	pub rebus: Option<String>,
+	pub flags: CellFlags,
+	pub annotation: Annotation,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SquareChange {
+	pub index: usize,
+	pub before: SquareState,
+	pub after: SquareState,
+}
+
+/// A single undoable step, with the squares it changed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Operation {
+	pub kind: OperationKind,
+	/// When the operation happened, or the last letter of coalesced typing
+	pub at: Duration,
+	pub changes: Vec<SquareChange>,
+}
+
+/// Undo and redo stacks of a `SolveSession`.
+///
+/// Letters entered in quick succession are coalesced into one operation, so
+/// a typed word is undone at once.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct History {
+	/// Maximum time between two letters to be coalesced
+	pub coalesce_within: Duration,
+	done: Vec<Operation>,
+	undone: Vec<Operation>,
+	/// Set after undo and redo, so new typing starts a new operation
+	coalescing_broken: bool,
+}
+
+impl Default for History {
+	fn default() -> Self {
+		Self {
+			coalesce_within: DEFAULT_COALESCE_WITHIN,
+			done: Vec::new(),
+			undone: Vec::new(),
+			coalescing_broken: false,
+		}
+	}
+}
+
+impl History {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// The operations that can be undone, oldest first
+	pub fn done(&self) -> &[Operation] {
+		&self.done
+	}
+
+	/// The operations that can be redone, most recently undone last
+	pub fn undone(&self) -> &[Operation] {
+		&self.undone
+	}
+
+	pub fn can_undo(&self) -> bool {
+		!self.done.is_empty()
+	}
+
+	pub fn can_redo(&self) -> bool {
+		!self.undone.is_empty()
+	}
+
+	/// Makes the next letter start a new operation, e.g. after the cursor
+	/// was moved
+	pub fn break_coalescing(&mut self) {
+		self.coalescing_broken = true;
+	}
+
+	/// Records an operation. Operations that did not change anything are
+	/// dropped, and the redo stack is discarded.
+	pub(crate) fn push(&mut self, operation: Operation) {
+		if operation.changes.is_empty() {
+			return;
+		}
+		self.undone.clear();
+
+		let coalescing_broken = core::mem::take(&mut self.coalescing_broken);
+		let previous = self.done.last_mut().filter(|previous| {
+			!coalescing_broken
+				&& previous.kind == OperationKind::Letter
+				&& operation.kind == OperationKind::Letter
+				&& operation.at.saturating_sub(previous.at) <= self.coalesce_within
+		});
+
+		let Some(previous) = previous else {
+			self.done.push(operation);
+			return;
+		};
+
+		for change in operation.changes {
+			match previous
+				.changes
+				.iter_mut()
+				.find(|previous| previous.index == change.index)
+			{
+				Some(previous) => previous.after = change.after,
+				None => previous.changes.push(change),
+			}
+		}
+		previous.at = operation.at;
+	}
+
+	pub(crate) fn undo(&mut self) -> Option<&Operation> {
+		let operation = self.done.pop()?;
+		self.undone.push(operation);
+		self.coalescing_broken = true;
+		self.undone.last()
+	}
+
+	pub(crate) fn redo(&mut self) -> Option<&Operation> {
+		let operation = self.undone.pop()?;
+		self.done.push(operation);
+		self.coalescing_broken = true;
+		self.done.last()
+	}
+
+	/// The largest square index of all operations
+	pub(crate) fn max_index(&self) -> Option<usize> {
+		self.done
+			.iter()
+			.chain(&self.undone)
+			.flat_map(|operation| &operation.changes)
+			.map(|change| change.index)
+			.max()
+	}
+
+	/// Serializes the undo and redo stacks.
+	///
+	/// The format starts with `PZHI`, a version byte and the coalescing time
+	/// in milliseconds (u64), followed by the done and the undone operations,
+	/// each preceded by their count (u32).
+	/// All numbers are little endian.
+	pub fn to_bytes(&self) -> Result<Vec<u8>, HistoryError> {
+		let mut bytes = Vec::new();
+		bytes.extend(HISTORY_MAGIC);
+		bytes.push(HISTORY_VERSION);
+		bytes.extend((self.coalesce_within.as_millis() as u64).to_le_bytes());
+
+		for operations in [&self.done, &self.undone] {
+			bytes.extend((operations.len() as u32).to_le_bytes());
+			for operation in operations {
+				write_operation(&mut bytes, operation)?;
+			}
+		}
+
+		Ok(bytes)
+	}
+
+	/// Restores a history serialized with `to_bytes`. Typing after restoring
+	/// never coalesces with the restored operations.
+	pub fn from_bytes(bytes: &[u8]) -> Result<Self, HistoryError> {
+		let mut reader = FieldReader::new(bytes, 0);
+
+		let magic: [u8; 4] = reader.array("history magic")?;
+		if &magic != HISTORY_MAGIC {
+			return Err(HistoryError::NotAHistory);
+		}
+		let version = reader.u8("history version")?;
+		if version != HISTORY_VERSION {
+			return Err(HistoryError::UnknownVersion(version));
+		}
+
+		let coalesce_within = Duration::from_millis(reader.u64("coalesce time")?);
+		let done = read_operations(&mut reader)?;
+		let undone = read_operations(&mut reader)?;
+
+		Ok(Self {
+			coalesce_within,
+			done,
+			undone,
+			coalescing_broken: true,
+		})
+	}
+}
+
+/// Operation: kind (u8), time in milliseconds (u64), number of changes (u32)
+/// and the changes. Change: square index (u32), state before, state after.
+fn write_operation(bytes: &mut Vec<u8>, operation: &Operation) -> Result<(), HistoryError> {
+	bytes.push(operation.kind.into());
+	bytes.extend((operation.at.as_millis() as u64).to_le_bytes());
+	bytes.extend((operation.changes.len() as u32).to_le_bytes());
+
+	for change in &operation.changes {
+		bytes.extend((change.index as u32).to_le_bytes());
+		write_square_state(bytes, &change.before)?;
+		write_square_state(bytes, &change.after)?;
+	}
+	Ok(())
+}
+
+/// Square state: cell (u8), flags (u8), the annotation as in the ANNO section
+/// and the UTF-8 rebus entry with its length (u16), or 0xFFFF without rebus.
+/// Longer entries are rejected, 0xFFFF would be read back as no rebus.
+fn write_square_state(bytes: &mut Vec<u8>, state: &SquareState) -> Result<(), HistoryError> {
+	bytes.push(state.cell.into());
+	bytes.push(state.flags.bits());
+	state
+		.annotation
+		.write(bytes)
+		.map_err(|_| HistoryError::TooManyCandidates(state.annotation.candidates.len()))?;
+	match &state.rebus {
+		Some(rebus) => {
+			let length = u16::try_from(rebus.len())
+				.ok()
+				.filter(|&length| length != u16::MAX)
+				.ok_or(HistoryError::RebusTooLong(rebus.len()))?;
+			bytes.extend(length.to_le_bytes());
+			bytes.extend(rebus.as_bytes());
+		}
+		None => bytes.extend(u16::MAX.to_le_bytes()),
+	}
+	Ok(())
+}
+
+/// Only the end of the data can fail reading
+impl From<ParsePuzError> for HistoryError {
+	fn from(_: ParsePuzError) -> Self {
+		HistoryError::Truncated
+	}
+}
+
+fn read_operations(reader: &mut FieldReader) -> Result<Vec<Operation>, HistoryError> {
+	let count = reader.u32("operation count")?;
+	let mut operations = Vec::new();
+
+	for _ in 0..count {
+		let kind = reader.u8("operation kind")?.try_into()?;
+		let at = Duration::from_millis(reader.u64("operation time")?);
+		let change_count = reader.u32("change count")?;
+
+		let mut changes = Vec::new();
+		for _ in 0..change_count {
+			changes.push(SquareChange {
+				index: reader.u32("square index")? as usize,
+				before: read_square_state(reader)?,
+				after: read_square_state(reader)?,
+			});
+		}
+
+		operations.push(Operation { kind, at, changes });
+	}
+
+	Ok(operations)
+}
+
+fn read_square_state(reader: &mut FieldReader) -> Result<SquareState, HistoryError> {
+	let cell = reader.u8("cell")?.into();
+	let flags = CellFlags::from_bits(reader.u8("cell flags")?);
+	let annotation = Annotation::read(reader).ok_or(HistoryError::InvalidAnnotation)?;
+
+	let rebus = match reader.u16("rebus length")? {
+		u16::MAX => None,
+		length => {
+			let rebus = reader.bytes("rebus", length as usize)?;
+			Some(core::str::from_utf8(rebus)?.to_owned())
+		}
+	};
+
+	Ok(SquareState {
+		cell,
+		rebus,
+		flags,
+		annotation,
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::solve::test_puzzle;
+	use crate::{CellFlags, Scope, SolveSession};
+	use std::sync::atomic::{AtomicU64, Ordering};
+
+	static NOW_MILLIS: AtomicU64 = AtomicU64::new(0);
+
+	fn fake_clock() -> Duration {
+		Duration::from_millis(NOW_MILLIS.load(Ordering::Relaxed))
+	}
+
+	fn session() -> SolveSession {
+		let mut session = SolveSession::new(test_puzzle());
+		session.set_clock(fake_clock);
+		session
+	}
+
+	#[test]
+	fn it_coalesces_typing() {
+		let mut session = session();
+		session.clear(Scope::Puzzle).unwrap();
+
+		NOW_MILLIS.store(10_000, Ordering::Relaxed);
+		session.enter_letter(6, b'A').unwrap();
+		NOW_MILLIS.store(10_300, Ordering::Relaxed);
+		session.enter_letter(7, b'R').unwrap();
+		NOW_MILLIS.store(10_600, Ordering::Relaxed);
+		session.enter_letter(8, b'T').unwrap();
+		NOW_MILLIS.store(15_000, Ordering::Relaxed);
+		session.enter_pencil(0, b'S').unwrap();
+
+		assert_eq!(session.history().done().len(), 3);
+		assert_eq!(session.puz().player_state.to_bytes(), b"S---.-ART");
+		assert!(session.is_pencil(0));
+
+		assert_eq!(session.undo(), Some(OperationKind::Pencil));
+		assert!(!session.is_pencil(0));
+		assert_eq!(session.undo(), Some(OperationKind::Letter));
+		assert_eq!(session.puz().player_state.to_bytes(), b"----.----");
+
+		assert_eq!(session.redo(), Some(OperationKind::Letter));
+		assert_eq!(session.puz().player_state.to_bytes(), b"----.-ART");
+
+		// Typing after an undo starts a new operation and drops the redo stack
+		session.enter_letter(3, b'E').unwrap();
+		assert_eq!(session.history().done().len(), 3);
+		assert!(!session.history().can_redo());
+	}
+
+	#[test]
+	fn it_undoes_reveals() {
+		let mut session = session();
+		let original = session.puz().clone();
+
+		session.check(Scope::Puzzle).unwrap();
+		session.reveal(Scope::Puzzle).unwrap();
+		assert_eq!(session.is_solved(), Ok(true));
+
+		assert_eq!(session.undo(), Some(OperationKind::Reveal));
+		let flags = session.puz().cell_flags.clone().unwrap();
+		assert_eq!(flags[7], CellFlags::INCORRECT);
+		assert_eq!(session.puz().player_state, original.player_state);
+		assert_eq!(session.puz().user_rebus, original.user_rebus);
+
+		assert_eq!(session.undo(), Some(OperationKind::Check));
+		assert!(session.puz().cell_flags.as_ref().unwrap()[7].is_empty());
+		assert_eq!(session.undo(), None);
+	}
+
+	#[test]
+	fn it_resumes_serialized_histories() {
+		let mut session = session();
+		session.enter_rebus(6, "AR").unwrap();
+		session.check(Scope::Puzzle).unwrap();
+		session.undo();
+
+		let bytes = session.history().to_bytes().unwrap();
+		let history = History::from_bytes(&bytes).unwrap();
+		assert_eq!(history.done(), session.history().done());
+		assert_eq!(history.undone(), session.history().undone());
+
+		let mut resumed = SolveSession::with_history(session.puz().clone(), history).unwrap();
+		assert_eq!(resumed.redo(), Some(OperationKind::Check));
+		assert_eq!(resumed.undo(), Some(OperationKind::Check));
+		assert_eq!(resumed.undo(), Some(OperationKind::Rebus));
+		assert_eq!(resumed.puz().player_state.cells[6], Cell::Letter(b'A'));
+		assert_eq!(resumed.puz().user_rebus.as_ref().unwrap()[6], None);
+
+		assert!(matches!(
+			History::from_bytes(&bytes[..bytes.len() - 1]),
+			Err(HistoryError::Truncated)
+		));
+		assert!(matches!(
+			History::from_bytes(b"HIST"),
+			Err(HistoryError::NotAHistory)
+		));
+	}
+
+	#[test]
+	fn it_rejects_rebus_entries_that_do_not_fit() {
+		let mut session = session();
+		session.enter_rebus(6, &"A".repeat(0xfffe)).unwrap();
+		let bytes = session.history().to_bytes().unwrap();
+		assert!(History::from_bytes(&bytes).is_ok());
+
+		session.enter_rebus(6, &"A".repeat(0xffff)).unwrap();
+		assert!(matches!(
+			session.history().to_bytes(),
+			Err(HistoryError::RebusTooLong(0xffff))
+		));
+	}
+}
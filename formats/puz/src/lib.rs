@@ -1,7 +1,59 @@
-use byteorder::{LittleEndian, ReadBytesExt};
-use std::io::{Cursor, Read, Seek, SeekFrom};
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+use alloc::{
+	string::{String, ToString},
+	vec::Vec,
+};
+use checksum::{EncodedStrings, HeaderChecksums};
+use diagnostics::Diagnostics;
+use extensions::RawSection;
+use reader::FieldReader;
 use thiserror::Error;
 
+mod borrowed;
+mod checksum;
+mod cosolve;
+mod diagnostics;
+mod embedded;
+mod error;
+mod extensions;
+mod grid;
+mod history;
+mod navigation;
+mod numbering;
+mod reader;
+mod scramble;
+mod solve;
+#[cfg(feature = "std")]
+mod stream;
+mod text;
+mod write;
+
+pub use borrowed::{parse_a_puz_ref, PuzFileRef, PuzStr};
+pub use checksum::{ChecksumRegion, MaskedChecksums};
+pub use cosolve::{CoOperation, CoSolveSession, Contents, PeerId, Timestamp};
+pub use diagnostics::{Diagnostic, DiagnosticKind, ParseMode, Severity};
+pub use embedded::{find_puzzles, EmbeddedPuz, EmbeddedPuzzles};
+pub use error::{hex_dump, ParseError};
+pub use extensions::{Annotation, CellFlags, Highlight, Rebus, RebusEntry, Timer, UnknownSection};
+pub use grid::{Cell, Grid};
+pub use history::{History, HistoryError, Operation, OperationKind, SquareChange, SquareState};
+pub use navigation::{Cursor, NavigationOptions, Navigator, Step};
+pub use numbering::{ClueCountMismatch, Direction, Entry, NumberedClue, Numbering};
+pub use scramble::{KeySearchProgress, ScrambleError};
+pub use solve::{Scope, SolveError, SolveSession};
+#[cfg(feature = "std")]
+pub use stream::{
+	read_puz, read_puz_seek, read_puz_with_diagnostics, MAX_PREAMBLE_LENGTH, MAX_SECTIONS_LENGTH,
+	MAX_STRINGS_LENGTH, MAX_STRING_LENGTH, MAX_TRAILING_LENGTH,
+};
+pub use text::TextEncoding;
+#[cfg(feature = "std")]
+pub use write::write_puz;
+pub use write::WritePuzError;
+
 #[derive(Error, Debug)]
 pub enum ParsePuzError {
 	#[error("this does not seem to be a .puz file - could not find beginning of puz data")]
@@ -14,11 +66,73 @@ pub enum ParsePuzError {
 	UnknownPuzzleType(u16),
 	#[error("unknown solution type: 0x{0:04x}")]
 	UnknownSolutionType(u16),
-	#[error("the puz file seems malformed or corrupted, could not find expected data")]
-	Malformed(#[from] std::io::Error),
+	#[error(
+		"expected {expected} strings (title, author, copyright, {clue_count} clues and notes), but only found {found}"
+	)]
+	StringCountMismatch {
+		clue_count: u16,
+		expected: usize,
+		found: usize,
+	},
+	#[error("string {index} is longer than {limit} bytes")]
+	StringTooLong { index: usize, limit: usize },
+	#[error("the strings are longer than {limit} bytes in total")]
+	StringsTooLong { limit: usize },
+	#[error("the extension sections are longer than {limit} bytes in total")]
+	SectionsTooLong { limit: usize },
+	#[error("the data after the puzzle is longer than {limit} bytes")]
+	TrailingTooLong { limit: usize },
+	#[error("no puzzle was found in the first {limit} bytes")]
+	PreambleTooLong { limit: usize },
+	#[error("extension section {} is cut off", String::from_utf8_lossy(.0))]
+	TruncatedSection([u8; 4]),
+	#[error("extension section {} is not terminated by a NUL byte", String::from_utf8_lossy(.0))]
+	UnterminatedSection([u8; 4]),
+	#[error(
+		"extension section {} should be {expected} bytes long, but is {found} bytes long",
+		String::from_utf8_lossy(.name)
+	)]
+	UnexpectedSectionLength {
+		name: [u8; 4],
+		expected: usize,
+		found: usize,
+	},
+	#[error("found a rebus table (RTBL) without a rebus board (GRBS)")]
+	RebusTableWithoutBoard,
+	#[error("malformed rebus table entry: '{0}'")]
+	MalformedRebusTable(String),
+	#[error(
+		"the rebus square at index {index} refers to key {key}, which is not in the rebus table"
+	)]
+	UnknownRebusKey { index: usize, key: u8 },
+	#[error(
+		"the user rebus section (RUSR) should contain {expected} strings, but contains {found}"
+	)]
+	UserRebusCountMismatch { expected: usize, found: usize },
+	#[error(
+		"the {region} checksum is 0x{expected:04x}, but the data has the checksum 0x{actual:04x}"
+	)]
+	ChecksumMismatch {
+		region: ChecksumRegion,
+		/// checksum stored in the file
+		expected: u16,
+		/// checksum calculated from the data
+		actual: u16,
+	},
+	#[error("a string in this puz file is not valid UTF-8")]
+	InvalidUtf8(#[from] core::str::Utf8Error),
+	#[error("strict parsing rejected the file: {0}")]
+	Rejected(Diagnostic),
+	#[error("expected {expected} more bytes, but only {found} are left")]
+	UnexpectedEnd { expected: usize, found: usize },
+	#[cfg(feature = "std")]
+	#[error("could not read the puz file")]
+	Io(#[from] std::io::Error),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum PuzzleType {
 	Normal,
 	Diagramless,
@@ -33,8 +147,18 @@ impl TryFrom<u16> for PuzzleType {
 		}
 	}
 }
+impl From<PuzzleType> for u16 {
+	fn from(value: PuzzleType) -> Self {
+		match value {
+			PuzzleType::Normal => 0x0001,
+			PuzzleType::Diagramless => 0x0401,
+		}
+	}
+}
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum SolutionType {
 	Normal,
 	Scrambled,
@@ -51,8 +175,19 @@ impl TryFrom<u16> for SolutionType {
 		}
 	}
 }
+impl From<SolutionType> for u16 {
+	fn from(value: SolutionType) -> Self {
+		match value {
+			SolutionType::Normal => 0x0000,
+			SolutionType::Missing => 0x0002,
+			SolutionType::Scrambled => 0x0004,
+		}
+	}
+}
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
 pub struct Crc16Checksum(u16);
 
 impl From<u16> for Crc16Checksum {
@@ -61,7 +196,14 @@ impl From<u16> for Crc16Checksum {
 	}
 }
 
-#[derive(Debug)]
+impl From<Crc16Checksum> for u16 {
+	fn from(value: Crc16Checksum) -> Self {
+		value.0
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PuzVersion {
 	/// first number of version tuple
 	pub major: u8,
@@ -88,9 +230,16 @@ impl TryFrom<[u8; 4]> for PuzVersion {
 		}
 	}
 }
+impl PuzVersion {
+	/// The notes only became part of the checksums in version 1.3
+	pub fn includes_notes_in_checksums(&self) -> bool {
+		(self.major, self.minor) >= (1, 3)
+	}
+}
 
 /// Data of unknown use, likely just garbage
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PuzGarbage {
 	/// There can be additional / unused data at the start of a puz file.
 	/// If some is found, it will be saved here, so it can be re-added when
@@ -104,9 +253,43 @@ pub struct PuzGarbage {
 	/// 12 bytes of unknown use.
 	/// Sometimes seems to be uninitialized data / random bits of strings
 	pub unknown_header_data_2: [u8; 12],
+
+	/// Extension sections that are not understood (or could not be read) are
+	/// kept here, so they can be re-added when saving the file.
+	pub unknown_sections: Vec<UnknownSection>,
+
+	/// The LTIM and RTBL sections as they were read. As long as the timer and
+	/// rebus table are unchanged, these are written back instead, so the
+	/// formatting of the text (e.g. "42, 1") is kept.
+	#[cfg_attr(feature = "serde", serde(default))]
+	pub text_sections: Vec<UnknownSection>,
+
+	/// Names of all extension sections in the order they appeared in the
+	/// file, so the order can be kept when saving the file.
+	pub section_order: Vec<[u8; 4]>,
+
+	/// There can be additional data after the last extension section.
+	/// If some is found, it will be saved here.
+	pub trailing: Option<Vec<u8>>,
 }
 
-#[derive(Debug)]
+/// A parsed puz file.
+///
+/// With the `serde` feature, the whole model can be serialised. The JSON
+/// shape uses the field names of the structs, and only changes with a new
+/// major version:
+///
+/// - checksums and cell flags are numbers
+/// - `version` is `{"major": 1, "minor": 3, "extension": null}`
+/// - `puzzle_type` and `solution_type` are snake case strings, e.g.
+///   `"diagramless"`
+/// - grids are `{"width": 3, "height": 3, "cells": [...]}`, with every cell
+///   as the byte stored in the file (46 for '.', 45 for '-', ...)
+/// - raw bytes (preamble, unknown header data, section data and names,
+///   trailing data) are arrays of numbers
+/// - missing optional data is `null`
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PuzFile {
 	pub garbage: PuzGarbage,
 
@@ -116,7 +299,9 @@ pub struct PuzFile {
 	/// checksum of metadata fields
 	pub checksum_board_configuration: Crc16Checksum,
 
-	pub masked_checksums: [u8; 8],
+	/// checksums of the board configuration, grids and strings, stored masked
+	/// with "ICHEATED"
+	pub masked_checksums: MaskedChecksums,
 
 	pub version: PuzVersion,
 
@@ -138,77 +323,470 @@ pub struct PuzFile {
 
 	// Solution Type
 	pub solution_type: SolutionType,
+
+	/// The solution board, may be scrambled or missing depending on the
+	/// solution type
+	pub solution: Grid,
+
+	/// The board as filled in by the player
+	pub player_state: Grid,
+
+	pub title: String,
+
+	pub author: String,
+
+	pub copyright: String,
+
+	/// Clues in numbering order, across before down for the same number
+	pub clues: Vec<String>,
+
+	pub notes: String,
+
+	/// Rebus solutions from the GRBS and RTBL extension sections
+	pub rebus: Option<Rebus>,
+
+	/// Flags of every cell (circled, revealed, ...) from the GEXT extension
+	/// section
+	pub cell_flags: Option<Vec<CellFlags>>,
+
+	/// Solving time from the LTIM extension section
+	pub timer: Option<Timer>,
+
+	/// Rebus entries of the player from the RUSR extension section
+	pub user_rebus: Option<Vec<Option<String>>>,
+
+	/// Pencil marks, candidates and highlights of every cell from the ANNO
+	/// extension section
+	pub annotations: Option<Vec<Annotation>>,
 }
 
 /// NUL-terminated constant string indicating start of file
 const FILE_MAGIC: &[u8; 12] = b"ACROSS&DOWN\0";
 
+/// Finds the start of the first puzzle, two bytes before the magic. Magic
+/// without room for the file checksum before it is skipped.
 fn get_puz_start_offset(puz_bytes: &[u8]) -> Result<usize, ParsePuzError> {
-	for i in 0_usize.. {
-		let sorry_sir_is_this_magic = puz_bytes
-			.get((i + 2)..(i + 14))
-			.ok_or(ParsePuzError::NotAPuz)?;
+	puz_bytes
+		.windows(FILE_MAGIC.len())
+		.skip(2)
+		.position(|sorry_sir_is_this_magic| sorry_sir_is_this_magic == FILE_MAGIC)
+		.ok_or(ParsePuzError::NotAPuz)
+}
 
-		if sorry_sir_is_this_magic == FILE_MAGIC {
-			return Ok(i);
-		}
-	}
+/// Like `get_puz_start_offset`, with the error pointing to the end of the
+/// bytes
+fn find_puz_start(puz_bytes: &[u8]) -> Result<usize, ParseError> {
+	get_puz_start_offset(puz_bytes).map_err(|kind| ParseError {
+		kind,
+		field: "file magic",
+		offset: puz_bytes.len(),
+		puz_start: None,
+	})
+}
 
-	unreachable!();
+pub fn parse_a_puz(puz_bytes: &[u8]) -> Result<PuzFile, ParseError> {
+	parse_a_puz_with_diagnostics(puz_bytes).map(|(puz, _)| puz)
 }
 
-pub fn parse_a_puz(puz_bytes: &[u8]) -> Result<PuzFile, ParsePuzError> {
-	let start_offset = get_puz_start_offset(puz_bytes)?;
+/// Like `parse_a_puz`, but also returns the problems that could be recovered
+/// from while parsing.
+pub fn parse_a_puz_with_diagnostics(
+	puz_bytes: &[u8],
+) -> Result<(PuzFile, Vec<Diagnostic>), ParseError> {
+	parse_a_puz_with_mode(puz_bytes, ParseMode::Normal)
+}
 
-	let preamble = if start_offset > 0 {
-		Some(Vec::from(&puz_bytes[0..start_offset]))
-	} else {
-		None
-	};
+/// Parses a puz file, dealing with problems as the mode says. The
+/// diagnostics are ordered by their position in the file.
+pub fn parse_a_puz_with_mode(
+	puz_bytes: &[u8],
+	mode: ParseMode,
+) -> Result<(PuzFile, Vec<Diagnostic>), ParseError> {
+	let start_offset = find_puz_start(puz_bytes)?;
 
-	let mut reader = Cursor::new(&puz_bytes[start_offset..]);
+	let mut reader = FieldReader::new(puz_bytes, start_offset);
+	parse_puz_at(&mut reader, start_offset, mode).map_err(|kind| ParseError {
+		kind,
+		field: reader.field,
+		offset: reader.position,
+		puz_start: Some(start_offset),
+	})
+}
 
-	let checksum: Crc16Checksum = reader.read_u16::<LittleEndian>()?.into();
+/// The fields of the header up to the clue count
+pub(crate) struct Header {
+	pub checksum: Crc16Checksum,
+	pub checksum_board_configuration: Crc16Checksum,
+	pub masked_checksums: MaskedChecksums,
+	pub version: PuzVersion,
+	pub unknown_header_data_1: [u8; 2],
+	pub checksum_scrambled: Option<Crc16Checksum>,
+	pub unknown_header_data_2: [u8; 12],
+	/// Position of the width, the start of the board configuration
+	pub board_configuration_start: usize,
+	pub width: u8,
+	pub height: u8,
+	pub clue_count: u16,
+}
 
-	reader.seek(SeekFrom::Current(12))?;
+/// Reads the header of the puzzle starting at the position of the reader
+pub(crate) fn read_header(reader: &mut FieldReader) -> Result<Header, ParsePuzError> {
+	let checksum: Crc16Checksum = reader.u16("file checksum")?.into();
 
-	let checksum_board_configuration: Crc16Checksum = reader.read_u16::<LittleEndian>()?.into();
+	reader.bytes("file magic", FILE_MAGIC.len())?;
 
-	let mut masked_checksums = [0_u8; 8];
-	reader.read_exact(&mut masked_checksums)?;
+	let checksum_board_configuration: Crc16Checksum =
+		reader.u16("board configuration checksum")?.into();
 
-	let mut version_bytes = [0_u8; 4];
-	reader.read_exact(&mut version_bytes)?;
-	let version = version_bytes.try_into()?;
+	let masked_checksums = MaskedChecksums::from_masked_bytes(reader.array("masked checksums")?);
 
-	let mut unknown_header_data_1 = [0_u8; 2];
-	reader.read_exact(&mut unknown_header_data_1)?;
+	let version: PuzVersion = reader.array::<4>("version")?.try_into()?;
 
-	let checksum_scrambled_raw = reader.read_u16::<LittleEndian>()?;
+	let unknown_header_data_1 = reader.array("unknown header data")?;
+
+	let checksum_scrambled_raw = reader.u16("scrambled checksum")?;
 	let checksum_scrambled = if checksum_scrambled_raw == 0 {
 		None
 	} else {
 		Some(checksum_scrambled_raw.into())
 	};
 
-	let mut unknown_header_data_2 = [0_u8; 12];
-	reader.read_exact(&mut unknown_header_data_2)?;
+	let unknown_header_data_2 = reader.array("unknown header data")?;
 
-	let width = reader.read_u8()?;
-	let height = reader.read_u8()?;
-	let clue_count = reader.read_u16::<LittleEndian>()?;
+	let board_configuration_start = reader.position;
+	let width = reader.u8("width")?;
+	let height = reader.u8("height")?;
+	let clue_count = reader.u16("clue count")?;
 
-	let puzzle_type = reader.read_u16::<LittleEndian>()?.try_into()?;
-	let solution_type = reader.read_u16::<LittleEndian>()?.try_into()?;
+	Ok(Header {
+		checksum,
+		checksum_board_configuration,
+		masked_checksums,
+		version,
+		unknown_header_data_1,
+		checksum_scrambled,
+		unknown_header_data_2,
+		board_configuration_start,
+		width,
+		height,
+		clue_count,
+	})
+}
+
+/// Parses the puz file starting at `start_offset`. The reader is left at the
+/// field that failed.
+fn parse_puz_at(
+	reader: &mut FieldReader,
+	start_offset: usize,
+	mode: ParseMode,
+) -> Result<(PuzFile, Vec<Diagnostic>), ParsePuzError> {
+	let mut diagnostics = Diagnostics::new(mode);
+	let puz_bytes = reader.bytes;
+
+	let preamble = if start_offset > 0 {
+		Some(Vec::from(&puz_bytes[0..start_offset]))
+	} else {
+		None
+	};
+
+	let Header {
+		checksum,
+		checksum_board_configuration,
+		masked_checksums,
+		version,
+		unknown_header_data_1,
+		checksum_scrambled,
+		unknown_header_data_2,
+		board_configuration_start,
+		width,
+		height,
+		clue_count,
+	} = read_header(reader)?;
+
+	let puzzle_type = reader.u16("puzzle type")?;
+	let puzzle_type = PuzzleType::try_from(puzzle_type).or_else(|error| {
+		diagnostics.report(
+			board_configuration_start + 4,
+			DiagnosticKind::UnknownPuzzleType(puzzle_type),
+			|| error,
+		)?;
+		Ok::<_, ParsePuzError>(PuzzleType::Normal)
+	})?;
+	let solution_type = reader.u16("solution type")?;
+	let solution_type = SolutionType::try_from(solution_type).or_else(|error| {
+		diagnostics.report(
+			board_configuration_start + 6,
+			DiagnosticKind::UnknownSolutionType(solution_type),
+			|| error,
+		)?;
+		Ok::<_, ParsePuzError>(SolutionType::Normal)
+	})?;
+
+	let board_size = width as usize * height as usize;
+
+	let solution_bytes = reader.bytes("solution", board_size)?;
+	let solution = Grid::from_bytes(width, height, solution_bytes);
+
+	let player_state_bytes = reader.bytes("player state", board_size)?;
+	let player_state = Grid::from_bytes(width, height, player_state_bytes);
 
 	// for strings the reader interface seems less helpful
-	let rest = &puz_bytes[(reader.position() as usize)..];
+	let strings_start = reader.position;
+	reader.field = "strings";
+	let rest = reader.rest();
+
+	let expected_strings = clue_count as usize + 4;
+	let (mut strings, strings_length) = match text::split_strings(rest, expected_strings) {
+		Ok(split) => split,
+		Err(found) => {
+			reader.at(puz_bytes.len(), "strings");
+			diagnostics.report(
+				puz_bytes.len(),
+				DiagnosticKind::MissingStrings {
+					expected: expected_strings,
+					found,
+				},
+				|| ParsePuzError::StringCountMismatch {
+					clue_count,
+					expected: expected_strings,
+					found,
+				},
+			)?;
+
+			// Keep a cut off string, the missing ones stay empty
+			let (mut strings, consumed) = text::split_strings(rest, found).unwrap();
+			strings.push(&rest[consumed..]);
+			(strings, rest.len())
+		}
+	};
+	strings.resize(expected_strings, b"");
+
+	let strings = EncodedStrings {
+		title: strings[0],
+		author: strings[1],
+		copyright: strings[2],
+		clues: strings[3..(expected_strings - 1)].to_vec(),
+		notes: strings[expected_strings - 1],
+	};
+
+	let board_configuration =
+		&puz_bytes[board_configuration_start..(board_configuration_start + 8)];
+	let actual_checksums = HeaderChecksums::calculate(
+		board_configuration,
+		solution_bytes,
+		player_state_bytes,
+		&strings,
+		version.includes_notes_in_checksums(),
+	);
+	reader.at(start_offset + 0x0E, "board configuration checksum");
+	checksum::verify(
+		&mut diagnostics,
+		reader.position,
+		ChecksumRegion::BoardConfiguration,
+		checksum_board_configuration,
+		actual_checksums.board_configuration,
+	)?;
+	reader.at(start_offset, "file checksum");
+	checksum::verify(
+		&mut diagnostics,
+		reader.position,
+		ChecksumRegion::File,
+		checksum,
+		actual_checksums.file,
+	)?;
+	for (index, (region, expected, actual)) in masked_checksums
+		.compare(&actual_checksums.masked)
+		.into_iter()
+		.enumerate()
+	{
+		reader.at(start_offset + 0x10 + index, "masked checksums");
+		checksum::verify(&mut diagnostics, reader.position, region, expected, actual)?;
+	}
+
+	let encoding = version.text_encoding();
+	let mut string_offset = strings_start;
+	let mut decode = |field, bytes: &[u8]| {
+		reader.at(string_offset, field);
+		string_offset += bytes.len() + 1;
+		encoding.decode(bytes).or_else(|error| {
+			diagnostics.report(reader.position, DiagnosticKind::InvalidUtf8, || error)?;
+			Ok::<_, ParsePuzError>(String::from_utf8_lossy(bytes).into_owned())
+		})
+	};
+	let title = decode("title", strings.title)?;
+	let author = decode("author", strings.author)?;
+	let copyright = decode("copyright", strings.copyright)?;
+	let clues = strings
+		.clues
+		.iter()
+		.map(|clue| decode("clue", clue))
+		.collect::<Result<_, _>>()?;
+	let notes = decode("notes", strings.notes)?;
+
+	let sections_start = strings_start + strings_length;
+	let (sections, trailing, broken) = extensions::split_sections(&puz_bytes[sections_start..]);
+	if let Some(error) = broken {
+		reader.at(puz_bytes.len() - trailing.len(), "extension section");
+		let name = match error {
+			ParsePuzError::TruncatedSection(name) | ParsePuzError::UnterminatedSection(name) => {
+				name
+			}
+			_ => unreachable!(),
+		};
+		diagnostics.warn(reader.position, DiagnosticKind::BrokenSection(name))?;
+	}
+	let find_section = |name: &[u8; 4]| sections.iter().find(|section| &section.name == name);
+
+	for section in &sections {
+		reader.at(
+			sections_start + section.offset + 6,
+			"extension section checksum",
+		);
+		checksum::verify(
+			&mut diagnostics,
+			reader.position,
+			ChecksumRegion::Section(section.name),
+			section.checksum.into(),
+			Crc16Checksum::of(section.data),
+		)?;
+	}
+
+	// Known sections that could not be read are kept as-is
+	let unreadable = |diagnostics: &mut Diagnostics,
+	                  reader: &mut FieldReader,
+	                  section: &RawSection,
+	                  error: ParsePuzError| {
+		reader.at(sections_start + section.offset, "extension section");
+		diagnostics.warn(
+			reader.position,
+			DiagnosticKind::UnreadableSection {
+				name: section.name,
+				reason: error.to_string(),
+			},
+		)
+	};
 
-	Ok(PuzFile {
+	let rebus = match (find_section(b"GRBS"), find_section(b"RTBL")) {
+		(Some(board), table) => {
+			let rebus = extensions::expect_board_length(board, board_size).and_then(|_| {
+				let table = table.map_or(&[][..], |table| table.data);
+				Rebus::from_sections(board.data, table, encoding)
+			});
+			match rebus {
+				Ok(rebus) => Some(rebus),
+				Err(error) => {
+					unreadable(&mut diagnostics, reader, board, error)?;
+					None
+				}
+			}
+		}
+		(None, Some(table)) => {
+			unreadable(
+				&mut diagnostics,
+				reader,
+				table,
+				ParsePuzError::RebusTableWithoutBoard,
+			)?;
+			None
+		}
+		(None, None) => None,
+	};
+
+	let cell_flags = match find_section(b"GEXT") {
+		Some(section) => match extensions::expect_board_length(section, board_size) {
+			Ok(()) => Some(
+				section
+					.data
+					.iter()
+					.map(|&bits| CellFlags::from_bits(bits))
+					.collect(),
+			),
+			Err(error) => {
+				unreadable(&mut diagnostics, reader, section, error)?;
+				None
+			}
+		},
+		None => None,
+	};
+
+	let timer = match find_section(b"LTIM") {
+		Some(section) => {
+			let timer = Timer::from_section(section.data);
+			if timer.is_none() {
+				reader.at(sections_start + section.offset, "extension section");
+				diagnostics.warn(
+					reader.position,
+					DiagnosticKind::MalformedTimer(
+						String::from_utf8_lossy(section.data).into_owned(),
+					),
+				)?;
+			}
+			timer
+		}
+		None => None,
+	};
+
+	let user_rebus = match find_section(b"RUSR") {
+		Some(section) => match extensions::parse_user_rebus(section.data, board_size, encoding) {
+			Ok(user_rebus) => Some(user_rebus),
+			Err(error) => {
+				unreadable(&mut diagnostics, reader, section, error)?;
+				None
+			}
+		},
+		None => None,
+	};
+
+	let annotations = match find_section(b"ANNO") {
+		Some(section) => {
+			let annotations = extensions::parse_annotations(section.data, board_size);
+			if annotations.is_none() {
+				reader.at(sections_start + section.offset, "extension section");
+				diagnostics.warn(reader.position, DiagnosticKind::MalformedAnnotations)?;
+			}
+			annotations
+		}
+		None => None,
+	};
+
+	// Only the first section of every known name is read. Repeated ones, and
+	// ones that could not be read, are kept as they are.
+	let unknown_sections = sections
+		.iter()
+		.enumerate()
+		.filter(|(index, section)| {
+			!extensions::KNOWN_SECTIONS.contains(&&section.name)
+				|| sections[..*index]
+					.iter()
+					.any(|previous| previous.name == section.name)
+				|| match &section.name {
+					b"GRBS" | b"RTBL" => rebus.is_none(),
+					b"GEXT" => cell_flags.is_none(),
+					b"LTIM" => timer.is_none(),
+					b"RUSR" => user_rebus.is_none(),
+					b"ANNO" => annotations.is_none(),
+					_ => false,
+				}
+		})
+		.map(|(_, section)| section.to_unknown())
+		.collect();
+
+	let text_sections = [(b"LTIM", timer.is_some()), (b"RTBL", rebus.is_some())]
+		.into_iter()
+		.filter(|&(_, read)| read)
+		.filter_map(|(name, _)| find_section(name))
+		.map(RawSection::to_unknown)
+		.collect();
+
+	let puz = PuzFile {
 		garbage: PuzGarbage {
 			preamble,
 			unknown_header_data_1,
 			unknown_header_data_2,
+			unknown_sections,
+			text_sections,
+			section_order: sections.iter().map(|section| section.name).collect(),
+			trailing: (!trailing.is_empty()).then(|| trailing.to_vec()),
 		},
 		checksum,
 		checksum_board_configuration,
@@ -220,7 +798,23 @@ pub fn parse_a_puz(puz_bytes: &[u8]) -> Result<PuzFile, ParsePuzError> {
 		clue_count,
 		puzzle_type,
 		solution_type,
-	})
+		solution,
+		player_state,
+		title,
+		author,
+		copyright,
+		clues,
+		notes,
+		rebus,
+		cell_flags,
+		timer,
+		user_rebus,
+		annotations,
+	};
+
+	let mut diagnostics = diagnostics.diagnostics;
+	diagnostics.sort_by_key(|diagnostic| diagnostic.offset);
+	Ok((puz, diagnostics))
 }
 
 #[cfg(test)]
@@ -237,4 +831,413 @@ mod tests {
 
 		dbg!(parsed);
 	}
+
+	#[test]
+	fn it_parses_the_grids() {
+		let puzzle = include_bytes!("../fixtures/test-no-solution.puz");
+
+		let parsed = parse_a_puz(puzzle).expect("Parsing Failed");
+
+		assert_eq!(parsed.solution.width, 3);
+		assert_eq!(parsed.solution.height, 3);
+		assert_eq!(parsed.solution.get(1, 1), Some(Cell::Block));
+		assert_eq!(parsed.player_state.get(0, 0), Some(Cell::Letter(b'C')));
+		assert_eq!(parsed.player_state.get(0, 2), Some(Cell::Empty));
+		assert_eq!(parsed.player_state.to_bytes(), b"CA--.----");
+	}
+
+	#[test]
+	fn it_decodes_utf8_strings() {
+		let puzzle = include_bytes!("../fixtures/test-no-solution.puz");
+
+		let parsed = parse_a_puz(puzzle).expect("Parsing Failed");
+
+		assert_eq!(parsed.title, "Grüße aus Köln");
+		assert_eq!(parsed.author, "Zoë Ölmann");
+		assert_eq!(parsed.clues.len(), 4);
+		assert_eq!(parsed.clues[0], "Haustier 🐈");
+		assert_eq!(parsed.notes, "Ünïcödé notes ✓");
+	}
+
+	#[test]
+	fn it_decodes_latin1_strings() {
+		let puzzle = include_bytes!("../fixtures/test-extensions.puz");
+
+		let parsed = parse_a_puz(puzzle).expect("Parsing Failed");
+
+		assert_eq!(parsed.title, "Café crème");
+		assert_eq!(parsed.copyright, "© 1998 Les Éditions");
+		assert_eq!(parsed.clues[0], "Étoile … du matin");
+		assert_eq!(parsed.notes, "Notes ¼ – œuvre");
+	}
+
+	#[test]
+	fn it_parses_rebus_squares() {
+		let puzzle = include_bytes!("../fixtures/test-extensions.puz");
+
+		let parsed = parse_a_puz(puzzle).expect("Parsing Failed");
+		let rebus = parsed.rebus.expect("rebus missing");
+
+		assert_eq!(rebus.solution_at(0), Some("STAR"));
+		assert_eq!(rebus.solution_at(1), None);
+		assert_eq!(rebus.solution_at(8), Some("TEA"));
+		assert_eq!(parsed.solution.cells[8], Cell::Letter(b'T'));
+	}
+
+	#[test]
+	fn it_parses_cell_flags() {
+		let puzzle = include_bytes!("../fixtures/test-extensions.puz");
+
+		let parsed = parse_a_puz(puzzle).expect("Parsing Failed");
+		let flags = parsed.cell_flags.expect("cell flags missing");
+
+		assert_eq!(flags.len(), 9);
+		assert!(flags[0].is_empty());
+		assert!(flags[1].contains(CellFlags::CIRCLED));
+		assert!(flags[3].contains(CellFlags::REVEALED));
+		assert!(flags[5].contains(CellFlags::PREVIOUSLY_INCORRECT));
+		assert!(flags[6].contains(CellFlags::INCORRECT));
+	}
+
+	#[test]
+	fn it_parses_timer_and_user_rebus() {
+		let puzzle = include_bytes!("../fixtures/test-extensions.puz");
+
+		let (parsed, diagnostics) = parse_a_puz_with_diagnostics(puzzle).expect("Parsing Failed");
+
+		assert_eq!(diagnostics, []);
+		assert_eq!(
+			parsed.timer,
+			Some(Timer {
+				elapsed_seconds: 42,
+				running: false
+			})
+		);
+		let user_rebus = parsed.user_rebus.expect("user rebus missing");
+		assert_eq!(user_rebus[0].as_deref(), Some("STAR"));
+		assert!(user_rebus[1..].iter().all(Option::is_none));
+	}
+
+	#[test]
+	fn it_keeps_unknown_sections() {
+		let puzzle = include_bytes!("../fixtures/test-extensions.puz");
+
+		let parsed = parse_a_puz(puzzle).expect("Parsing Failed");
+		let garbage = parsed.garbage;
+
+		assert_eq!(garbage.preamble.as_deref(), Some(&b"\0\0PREAMBLE"[..]));
+		assert_eq!(
+			garbage.section_order,
+			[*b"GRBS", *b"RTBL", *b"XTRA", *b"LTIM", *b"GEXT", *b"RUSR"]
+		);
+		assert_eq!(garbage.unknown_sections.len(), 1);
+		assert_eq!(&garbage.unknown_sections[0].name, b"XTRA");
+		assert_eq!(garbage.unknown_sections[0].data, b"hello, world");
+		assert_eq!(garbage.trailing, None);
+	}
+
+	#[test]
+	fn it_verifies_checksums() {
+		let puzzle = include_bytes!("../fixtures/test-extensions.puz");
+
+		let mut corrupted_title = puzzle.to_vec();
+		corrupted_title[0x50] = b'K';
+		assert!(matches!(
+			parse_a_puz(&corrupted_title).map_err(|error| error.kind),
+			Err(ParsePuzError::ChecksumMismatch {
+				region: ChecksumRegion::File,
+				..
+			})
+		));
+
+		let mut corrupted_size = puzzle.to_vec();
+		corrupted_size[0x37] = 2;
+		assert!(matches!(
+			parse_a_puz(&corrupted_size).map_err(|error| error.kind),
+			Err(ParsePuzError::ChecksumMismatch {
+				region: ChecksumRegion::BoardConfiguration,
+				..
+			})
+		));
+
+		let mut corrupted_section = puzzle.to_vec();
+		let timer_position = puzzle.windows(4).position(|bytes| bytes == b"42,1");
+		let timer_position = timer_position.unwrap();
+		corrupted_section[timer_position] = b'5';
+		assert!(matches!(
+			parse_a_puz(&corrupted_section).map_err(|error| error.kind),
+			Err(ParsePuzError::ChecksumMismatch {
+				region: ChecksumRegion::Section(name),
+				expected: 0x005a,
+				actual: 0x205a,
+			}) if &name == b"LTIM"
+		));
+	}
+
+	#[test]
+	fn it_verifies_masked_checksums() {
+		let puzzle = include_bytes!("../fixtures/test-extensions.puz");
+
+		let parsed = parse_a_puz(puzzle).expect("Parsing Failed");
+		assert_eq!(
+			parsed.masked_checksums.board_configuration,
+			parsed.checksum_board_configuration
+		);
+
+		let mut corrupted = puzzle.to_vec();
+		// low byte of the masked solution checksum
+		corrupted[10 + 0x11] ^= 0x01;
+		assert!(matches!(
+			parse_a_puz(&corrupted).map_err(|error| error.kind),
+			Err(ParsePuzError::ChecksumMismatch {
+				region: ChecksumRegion::MaskedSolution,
+				..
+			})
+		));
+	}
+
+	#[test]
+	fn it_fails_on_missing_strings() {
+		let puzzle = include_bytes!("../fixtures/test-no-solution.puz");
+		// cut off within the third clue
+		let truncated = &puzzle[..0xb0];
+
+		assert!(matches!(
+			parse_a_puz(truncated).map_err(|error| error.kind),
+			Err(ParsePuzError::StringCountMismatch {
+				clue_count: 4,
+				expected: 8,
+				found: 5
+			})
+		));
+	}
+
+	#[test]
+	fn it_recovers_from_broken_files_in_lenient_mode() {
+		let puzzle = include_bytes!("../fixtures/test-extensions.puz");
+
+		let mut corrupted = puzzle.to_vec();
+		corrupted[0x50] = b'K';
+		// unknown solution type
+		corrupted[10 + 0x32] = 0x99;
+		let (parsed, diagnostics) =
+			parse_a_puz_with_mode(&corrupted, ParseMode::Lenient).expect("Parsing Failed");
+
+		assert_eq!(parsed.solution_type, SolutionType::Normal);
+		let kinds: Vec<_> = diagnostics
+			.iter()
+			.map(|diagnostic| &diagnostic.kind)
+			.collect();
+		assert!(matches!(
+			kinds[..],
+			[
+				DiagnosticKind::ChecksumMismatch {
+					region: ChecksumRegion::File,
+					..
+				},
+				DiagnosticKind::ChecksumMismatch {
+					region: ChecksumRegion::BoardConfiguration,
+					..
+				},
+				DiagnosticKind::ChecksumMismatch {
+					region: ChecksumRegion::MaskedBoardConfiguration,
+					..
+				},
+				DiagnosticKind::ChecksumMismatch {
+					region: ChecksumRegion::MaskedStrings,
+					..
+				},
+				DiagnosticKind::UnknownSolutionType(0x0099),
+			]
+		));
+		assert_eq!(diagnostics[0].offset, 10);
+		assert_eq!(diagnostics[4].offset, 10 + 0x32);
+		assert!(diagnostics
+			.iter()
+			.all(|diagnostic| diagnostic.severity() == Severity::Error));
+
+		// cut off within the notes
+		let notes_position = puzzle.windows(5).position(|bytes| bytes == b"Notes");
+		let truncated = &puzzle[..(notes_position.unwrap() + 5)];
+		let (parsed, diagnostics) =
+			parse_a_puz_with_mode(truncated, ParseMode::Lenient).expect("Parsing Failed");
+
+		assert_eq!(parsed.notes, "Notes");
+		assert_eq!(parsed.clues.len(), 4);
+		assert_eq!(
+			diagnostics.last().map(|diagnostic| &diagnostic.kind),
+			Some(&DiagnosticKind::MissingStrings {
+				expected: 8,
+				found: 7
+			})
+		);
+	}
+
+	#[test]
+	fn it_keeps_broken_sections_as_trailing_data() {
+		let puzzle = include_bytes!("../fixtures/test-extensions.puz");
+		let truncated = &puzzle[..(puzzle.len() - 4)];
+		assert!(matches!(
+			parse_a_puz_with_mode(truncated, ParseMode::Strict).map_err(|error| error.kind),
+			Err(ParsePuzError::Rejected(Diagnostic {
+				kind: DiagnosticKind::BrokenSection(name),
+				..
+			})) if &name == b"RUSR"
+		));
+
+		let (parsed, diagnostics) =
+			parse_a_puz_with_diagnostics(truncated).expect("Parsing Failed");
+		let rusr_position = puzzle.windows(4).position(|bytes| bytes == b"RUSR");
+
+		assert_eq!(parsed.user_rebus, None);
+		assert_eq!(
+			parsed.garbage.trailing.as_deref(),
+			Some(&truncated[rusr_position.unwrap()..])
+		);
+		assert_eq!(
+			diagnostics,
+			[Diagnostic {
+				offset: rusr_position.unwrap(),
+				kind: DiagnosticKind::BrokenSection(*b"RUSR"),
+			}]
+		);
+		assert_eq!(parsed.to_bytes().unwrap(), truncated);
+	}
+
+	#[test]
+	fn it_keeps_trailing_garbage() {
+		let puzzle = include_bytes!("../fixtures/test-no-solution.puz");
+
+		for garbage in [
+			&b"\0\0trailing garbage that is long"[..],
+			b"trailing garbage",
+		] {
+			let mut bytes = puzzle.to_vec();
+			bytes.extend(garbage);
+
+			let (parsed, diagnostics) =
+				parse_a_puz_with_diagnostics(&bytes).expect("Parsing Failed");
+			assert_eq!(parsed.garbage.trailing.as_deref(), Some(garbage));
+			assert_eq!(parsed.garbage.section_order, [] as [[u8; 4]; 0]);
+			assert!(diagnostics
+				.iter()
+				.all(|diagnostic| diagnostic.severity() == Severity::Warning));
+			assert_eq!(parsed.to_bytes().unwrap(), bytes);
+		}
+	}
+
+	#[test]
+	fn it_keeps_unreadable_sections_in_normal_mode() {
+		let puzzle = include_bytes!("../fixtures/test-extensions.puz");
+		let mut puz = parse_a_puz(puzzle).expect("Parsing Failed");
+		puz.cell_flags = None;
+		puz.garbage.unknown_sections.push(UnknownSection {
+			name: *b"GEXT",
+			checksum: Crc16Checksum::of(b"\0\0\0"),
+			data: b"\0\0\0".to_vec(),
+		});
+		let bytes = puz.to_bytes().unwrap();
+
+		let (parsed, diagnostics) = parse_a_puz_with_diagnostics(&bytes).expect("Parsing Failed");
+		assert_eq!(parsed.cell_flags, None);
+		assert!(matches!(
+			&diagnostics[..],
+			[Diagnostic {
+				kind: DiagnosticKind::UnreadableSection { name, .. },
+				..
+			}] if name == b"GEXT"
+		));
+		assert_eq!(diagnostics[0].severity(), Severity::Warning);
+		assert_eq!(parsed.to_bytes().unwrap(), bytes);
+	}
+
+	#[test]
+	fn it_rejects_warnings_in_strict_mode() {
+		let puzzle = include_bytes!("../fixtures/test-extensions.puz");
+		let mut puz = parse_a_puz(puzzle).expect("Parsing Failed");
+		puz.timer = None;
+		puz.garbage.unknown_sections.push(UnknownSection {
+			name: *b"LTIM",
+			checksum: Crc16Checksum::of(b"soon"),
+			data: b"soon".to_vec(),
+		});
+		let bytes = puz.to_bytes().unwrap();
+
+		let (parsed, diagnostics) = parse_a_puz_with_diagnostics(&bytes).expect("Parsing Failed");
+		assert_eq!(parsed.timer, None);
+		assert_eq!(diagnostics.len(), 1);
+		assert_eq!(diagnostics[0].severity(), Severity::Warning);
+		assert_eq!(parsed.to_bytes().unwrap(), bytes);
+
+		assert!(matches!(
+			parse_a_puz_with_mode(&bytes, ParseMode::Strict).map_err(|error| error.kind),
+			Err(ParsePuzError::Rejected(Diagnostic {
+				kind: DiagnosticKind::MalformedTimer(timer),
+				..
+			})) if timer == "soon"
+		));
+		assert!(parse_a_puz_with_mode(puzzle, ParseMode::Strict).is_ok());
+	}
+
+	#[test]
+	fn it_reports_where_errors_happen() {
+		let puzzle = include_bytes!("../fixtures/test-extensions.puz");
+
+		// cut off within the solution
+		let truncated = &puzzle[..(10 + 0x34 + 5)];
+		let error = parse_a_puz(truncated).unwrap_err();
+		assert!(matches!(
+			error.kind,
+			ParsePuzError::UnexpectedEnd {
+				expected: 9,
+				found: 5
+			}
+		));
+		assert_eq!(error.field, "solution");
+		assert_eq!(error.offset, 10 + 0x34);
+		assert_eq!(error.relative_offset(), Some(0x34));
+		assert_eq!(
+			error.to_string(),
+			"expected 9 more bytes, but only 5 are left (solution at byte 0x3e, 0x34 into the puzzle)"
+		);
+		// the marker is below the first byte of the solution
+		assert!(error
+			.hex_dump(truncated)
+			.contains(&format!("|\n{}^^\n", " ".repeat(10 + 3 * 14))));
+
+		let mut corrupted = puzzle.to_vec();
+		// height
+		corrupted[10 + 0x2D] = 2;
+		let error = parse_a_puz(&corrupted).unwrap_err();
+		assert_eq!(error.field, "board configuration checksum");
+		assert_eq!(error.relative_offset(), Some(0x0E));
+
+		let error = parse_a_puz(b"ACROSS&DOWN").unwrap_err();
+		assert!(matches!(error.kind, ParsePuzError::NotAPuz));
+		assert_eq!(error.relative_offset(), None);
+	}
+
+	#[cfg(feature = "serde")]
+	#[test]
+	fn it_serializes_to_json() {
+		let puzzle = include_bytes!("../fixtures/test-extensions.puz");
+		let parsed = parse_a_puz(puzzle).expect("Parsing Failed");
+
+		let json = serde_json::to_value(&parsed).unwrap();
+		assert_eq!(
+			json["version"],
+			serde_json::json!({"major": 1, "minor": 3, "extension": null})
+		);
+		assert_eq!(json["puzzle_type"], "normal");
+		assert_eq!(json["solution"]["cells"][4], b'.');
+		assert_eq!(json["timer"]["elapsed_seconds"], 42);
+		assert_eq!(
+			json["garbage"]["section_order"][0],
+			serde_json::json!(b"GRBS")
+		);
+
+		let deserialized: PuzFile = serde_json::from_value(json).unwrap();
+		assert_eq!(deserialized, parsed);
+		assert_eq!(deserialized.to_bytes().unwrap(), puzzle);
+	}
 }
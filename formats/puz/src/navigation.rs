@@ -0,0 +1,416 @@
+use crate::{Cell, Direction, Entry, Numbering, PuzFile, PuzzleType};
+use alloc::vec::Vec;
+
+/// The square being edited and the direction of typing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cursor {
+	pub index: usize,
+	pub direction: Direction,
+}
+
+/// A single step of the cursor, e.g. from an arrow key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+	Up,
+	Down,
+	Left,
+	Right,
+}
+
+impl Step {
+	fn direction(self) -> Direction {
+		match self {
+			Self::Left | Self::Right => Direction::Across,
+			Self::Up | Self::Down => Direction::Down,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NavigationOptions {
+	/// Skip squares that are already filled when advancing and moving
+	/// between words
+	pub skip_filled: bool,
+	/// Continue with the first word after the last one (and the other way
+	/// around) when moving between words
+	pub wrap: bool,
+}
+
+impl Default for NavigationOptions {
+	fn default() -> Self {
+		Self {
+			skip_filled: false,
+			wrap: true,
+		}
+	}
+}
+
+/// Cursor movement over the board of a puzzle.
+///
+/// The board is passed to every movement, so it can change in between.
+/// For diagramless puzzles the blocks are not fixed: the words are taken from
+/// the blocks the player placed, and the cursor can stop on any square.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Navigator {
+	pub cursor: Cursor,
+	pub options: NavigationOptions,
+}
+
+fn can_stop_at(puz: &PuzFile, index: usize) -> bool {
+	puz.puzzle_type == PuzzleType::Diagramless || !puz.solution.cells[index].is_block()
+}
+
+fn is_filled(puz: &PuzFile, index: usize) -> bool {
+	matches!(puz.player_state.cells[index], Cell::Letter(_))
+}
+
+/// All entries in the order words are visited: across, then down
+fn word_order(numbering: &Numbering) -> Vec<&Entry> {
+	numbering.across().chain(numbering.down()).collect()
+}
+
+impl Navigator {
+	/// Places the cursor at the start of the first across word
+	pub fn new(puz: &PuzFile, options: NavigationOptions) -> Self {
+		let numbering = puz.player_numbering();
+		let index = word_order(&numbering)
+			.first()
+			.map_or(0, |entry| entry.start);
+
+		Self {
+			cursor: Cursor {
+				index,
+				direction: Direction::Across,
+			},
+			options,
+		}
+	}
+
+	/// The word the cursor is in
+	pub fn current_entry(&self, puz: &PuzFile) -> Option<Entry> {
+		puz.player_numbering()
+			.entry_at(self.cursor.index, self.cursor.direction)
+			.cloned()
+	}
+
+	pub fn toggle_direction(&mut self) {
+		self.cursor.direction = self.cursor.direction.toggled();
+	}
+
+	/// Moves the cursor to the given square and direction
+	pub fn jump_to(&mut self, index: usize, direction: Direction) {
+		self.cursor = Cursor { index, direction };
+	}
+
+	/// Moves one square in the given direction, jumping over blocks. If the
+	/// step is across the current direction, only the direction is changed.
+	pub fn step(&mut self, puz: &PuzFile, step: Step) {
+		if step.direction() != self.cursor.direction {
+			self.cursor.direction = step.direction();
+			return;
+		}
+
+		let grid = &puz.solution;
+		if grid.cells.is_empty() {
+			return;
+		}
+		let (mut row, mut col) = grid.position(self.cursor.index);
+
+		loop {
+			let next = match step {
+				Step::Up => row.checked_sub(1).map(|row| (row, col)),
+				Step::Down => Some((row + 1, col)),
+				Step::Left => col.checked_sub(1).map(|col| (row, col)),
+				Step::Right => Some((row, col + 1)),
+			};
+			let Some(index) = next.and_then(|(row, col)| grid.index(row, col)) else {
+				return;
+			};
+
+			if can_stop_at(puz, index) {
+				self.cursor.index = index;
+				return;
+			}
+			(row, col) = grid.position(index);
+		}
+	}
+
+	/// Moves to the next square of the current word after entering a letter.
+	/// At the end of the word, the cursor moves on to the next word.
+	pub fn advance(&mut self, puz: &PuzFile) {
+		let Some(entry) = self.current_entry(puz) else {
+			return self.next_word(puz);
+		};
+
+		let position = entry
+			.cells
+			.iter()
+			.position(|&index| index == self.cursor.index)
+			.unwrap();
+		let next = entry.cells[(position + 1)..]
+			.iter()
+			.find(|&&index| !self.options.skip_filled || !is_filled(puz, index));
+
+		match next {
+			Some(&index) => self.cursor.index = index,
+			None => self.next_word(puz),
+		}
+	}
+
+	/// Moves to the previous square of the current word, or to the end of the
+	/// previous word
+	pub fn retreat(&mut self, puz: &PuzFile) {
+		let Some(entry) = self.current_entry(puz) else {
+			return self.previous_word(puz);
+		};
+
+		let position = entry
+			.cells
+			.iter()
+			.position(|&index| index == self.cursor.index)
+			.unwrap();
+
+		if position > 0 {
+			self.cursor.index = entry.cells[position - 1];
+		} else {
+			// Without wrapping, the first word has no previous word
+			let cursor = self.cursor;
+			self.move_to_word(puz, false);
+			if self.cursor == cursor {
+				return;
+			}
+			if let Some(entry) = self.current_entry(puz) {
+				self.cursor.index = *entry.cells.last().unwrap();
+			}
+		}
+	}
+
+	/// Moves to the next word in clue order
+	pub fn next_word(&mut self, puz: &PuzFile) {
+		self.move_to_word(puz, true);
+	}
+
+	/// Moves to the previous word in clue order
+	pub fn previous_word(&mut self, puz: &PuzFile) {
+		self.move_to_word(puz, false);
+	}
+
+	fn move_to_word(&mut self, puz: &PuzFile, forward: bool) {
+		let numbering = puz.player_numbering();
+		let order = word_order(&numbering);
+		if order.is_empty() {
+			return;
+		}
+
+		let Cursor { index, direction } = self.cursor;
+		let current = order
+			.iter()
+			.position(|entry| entry.direction == direction && entry.cells.contains(&index));
+
+		// Outside of a word, continue with the words around the cursor
+		let (before, after) = match current {
+			Some(current) => (current, current + 1),
+			None => {
+				let after = order
+					.iter()
+					.position(|entry| {
+						entry.direction == direction && entry.start > index
+							|| entry.direction == Direction::Down && direction == Direction::Across
+					})
+					.unwrap_or(order.len());
+				(after, after)
+			}
+		};
+
+		let candidates: Vec<usize> = if forward {
+			let wrapped = if self.options.wrap { 0..before } else { 0..0 };
+			(after..order.len()).chain(wrapped).collect()
+		} else {
+			let wrapped = if self.options.wrap {
+				after..order.len()
+			} else {
+				0..0
+			};
+			(0..before).rev().chain(wrapped.rev()).collect()
+		};
+
+		let first_empty = |entry: &Entry| {
+			entry
+				.cells
+				.iter()
+				.copied()
+				.find(|&index| !is_filled(puz, index))
+		};
+
+		let target = if self.options.skip_filled {
+			candidates
+				.iter()
+				.find_map(|&candidate| Some((order[candidate], first_empty(order[candidate])?)))
+		} else {
+			None
+		};
+		let target = target.or_else(|| {
+			candidates
+				.first()
+				.map(|&candidate| (order[candidate], order[candidate].start))
+		});
+
+		if let Some((entry, index)) = target {
+			self.cursor = Cursor {
+				index,
+				direction: entry.direction,
+			};
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{parse_a_puz, Grid};
+
+	fn puz() -> PuzFile {
+		// SUN    SU-
+		// E.O    N.-
+		// ART    AT-
+		let puzzle = include_bytes!("../fixtures/test-extensions.puz");
+		parse_a_puz(puzzle).expect("Parsing Failed")
+	}
+
+	fn cursor(index: usize, direction: Direction) -> Cursor {
+		Cursor { index, direction }
+	}
+
+	#[test]
+	fn it_moves_between_words() {
+		let puz = puz();
+		let mut navigator = Navigator::new(&puz, NavigationOptions::default());
+		assert_eq!(navigator.cursor, cursor(0, Direction::Across));
+
+		let mut visited = Vec::new();
+		for _ in 0..4 {
+			navigator.next_word(&puz);
+			visited.push(navigator.cursor);
+		}
+		assert_eq!(
+			visited,
+			[
+				cursor(6, Direction::Across),
+				cursor(0, Direction::Down),
+				cursor(2, Direction::Down),
+				cursor(0, Direction::Across),
+			]
+		);
+
+		navigator.previous_word(&puz);
+		assert_eq!(navigator.cursor, cursor(2, Direction::Down));
+
+		navigator.options.wrap = false;
+		navigator.next_word(&puz);
+		assert_eq!(navigator.cursor, cursor(2, Direction::Down));
+	}
+
+	#[test]
+	fn it_skips_filled_squares() {
+		let puz = puz();
+		let options = NavigationOptions {
+			skip_filled: true,
+			wrap: true,
+		};
+		let mut navigator = Navigator::new(&puz, options);
+
+		navigator.advance(&puz);
+		assert_eq!(navigator.cursor, cursor(2, Direction::Across));
+
+		navigator.next_word(&puz);
+		assert_eq!(navigator.cursor, cursor(8, Direction::Across));
+
+		// 1 down is completely filled
+		navigator.next_word(&puz);
+		assert_eq!(navigator.cursor, cursor(2, Direction::Down));
+	}
+
+	#[test]
+	fn it_advances_and_retreats() {
+		let puz = puz();
+		let mut navigator = Navigator::new(&puz, NavigationOptions::default());
+
+		navigator.advance(&puz);
+		navigator.advance(&puz);
+		assert_eq!(navigator.cursor, cursor(2, Direction::Across));
+
+		navigator.advance(&puz);
+		assert_eq!(navigator.cursor, cursor(6, Direction::Across));
+
+		navigator.retreat(&puz);
+		assert_eq!(navigator.cursor, cursor(2, Direction::Across));
+
+		// There is nothing before the first word without wrapping
+		navigator.options.wrap = false;
+		navigator.jump_to(0, Direction::Across);
+		navigator.retreat(&puz);
+		assert_eq!(navigator.cursor, cursor(0, Direction::Across));
+
+		navigator.options.wrap = true;
+		navigator.retreat(&puz);
+		assert_eq!(navigator.cursor, cursor(8, Direction::Down));
+	}
+
+	#[test]
+	fn it_steps_over_blocks() {
+		let puz = puz();
+		let mut navigator = Navigator::new(&puz, NavigationOptions::default());
+		navigator.jump_to(5, Direction::Down);
+
+		navigator.step(&puz, Step::Left);
+		assert_eq!(navigator.cursor, cursor(5, Direction::Across));
+
+		navigator.step(&puz, Step::Left);
+		assert_eq!(navigator.cursor, cursor(3, Direction::Across));
+
+		navigator.step(&puz, Step::Left);
+		assert_eq!(navigator.cursor, cursor(3, Direction::Across));
+	}
+
+	#[test]
+	fn it_stays_put_in_empty_puzzles() {
+		let mut puz = puz();
+		puz.width = 0;
+		puz.height = 0;
+		puz.solution = Grid::from_bytes(0, 0, b"");
+		puz.player_state = Grid::from_bytes(0, 0, b"");
+		puz.clue_count = 0;
+		puz.clues.clear();
+		puz.rebus = None;
+		puz.cell_flags = None;
+		puz.user_rebus = None;
+
+		let mut navigator = Navigator::new(&puz, NavigationOptions::default());
+		for step in [Step::Up, Step::Down, Step::Left, Step::Right] {
+			navigator.step(&puz, step);
+		}
+		navigator.advance(&puz);
+		navigator.retreat(&puz);
+		navigator.next_word(&puz);
+		assert_eq!(navigator.cursor, cursor(0, Direction::Across));
+	}
+
+	#[test]
+	fn it_uses_the_players_diagram_in_diagramless_puzzles() {
+		let mut puz = puz();
+		puz.puzzle_type = PuzzleType::Diagramless;
+		puz.player_state = Grid::from_bytes(3, 3, b"---------");
+
+		let mut navigator = Navigator::new(&puz, NavigationOptions::default());
+		navigator.jump_to(3, Direction::Across);
+		navigator.step(&puz, Step::Right);
+		assert_eq!(navigator.cursor, cursor(4, Direction::Across));
+		assert_eq!(navigator.current_entry(&puz).unwrap().cells, [3, 4, 5]);
+
+		puz.player_state.cells[4] = Cell::DiagramlessBlock;
+		assert_eq!(navigator.current_entry(&puz), None);
+
+		navigator.next_word(&puz);
+		assert_eq!(navigator.cursor, cursor(6, Direction::Across));
+	}
+}
@@ -0,0 +1,280 @@
+use crate::{Grid, PuzFile, PuzzleType};
+use alloc::{vec, vec::Vec};
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[error("the board has {entries} entries, but there are {clues} clues")]
+pub struct ClueCountMismatch {
+	pub entries: usize,
+	pub clues: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+	Across,
+	Down,
+}
+
+impl Direction {
+	pub fn toggled(self) -> Self {
+		match self {
+			Self::Across => Self::Down,
+			Self::Down => Self::Across,
+		}
+	}
+}
+
+/// A run of at least two white squares in one direction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+	pub number: u16,
+	pub direction: Direction,
+	/// Index of the first cell of the entry
+	pub start: usize,
+	/// Indices of all cells of the entry, in reading order
+	pub cells: Vec<usize>,
+}
+
+impl Entry {
+	pub fn length(&self) -> usize {
+		self.cells.len()
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Numbering {
+	/// The number printed in each cell, if any
+	pub cell_numbers: Vec<Option<u16>>,
+	/// All entries in clue order: by number, across before down
+	pub entries: Vec<Entry>,
+}
+
+impl Numbering {
+	pub fn across(&self) -> impl Iterator<Item = &Entry> {
+		self.entries
+			.iter()
+			.filter(|entry| entry.direction == Direction::Across)
+	}
+
+	pub fn down(&self) -> impl Iterator<Item = &Entry> {
+		self.entries
+			.iter()
+			.filter(|entry| entry.direction == Direction::Down)
+	}
+
+	/// The entry in the given direction that contains the given cell
+	pub fn entry_at(&self, index: usize, direction: Direction) -> Option<&Entry> {
+		self.entries
+			.iter()
+			.find(|entry| entry.direction == direction && entry.cells.contains(&index))
+	}
+}
+
+/// An entry together with its clue text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NumberedClue<'a> {
+	pub entry: Entry,
+	pub text: &'a str,
+}
+
+impl Grid {
+	fn is_white(&self, row: u8, col: u8) -> bool {
+		self.get(row, col).is_some_and(|cell| !cell.is_block())
+	}
+
+	/// Numbers the board using the usual rules: a white square gets the next
+	/// number if it starts an across or down run of at least two squares.
+	pub fn numbering(&self) -> Numbering {
+		let mut cell_numbers = vec![None; self.cells.len()];
+		let mut entries = Vec::new();
+		let mut number = 0;
+
+		for row in 0..self.height {
+			for col in 0..self.width {
+				if !self.is_white(row, col) {
+					continue;
+				}
+
+				let starts_across =
+					(col == 0 || !self.is_white(row, col - 1)) && self.is_white(row, col + 1);
+				let starts_down =
+					(row == 0 || !self.is_white(row - 1, col)) && self.is_white(row + 1, col);
+
+				if !starts_across && !starts_down {
+					continue;
+				}
+
+				number += 1;
+				let start = self.index(row, col).unwrap();
+				cell_numbers[start] = Some(number);
+
+				if starts_across {
+					let cells = (col..self.width)
+						.take_while(|&col| self.is_white(row, col))
+						.map(|col| self.index(row, col).unwrap())
+						.collect();
+					entries.push(Entry {
+						number,
+						direction: Direction::Across,
+						start,
+						cells,
+					});
+				}
+
+				if starts_down {
+					let cells = (row..self.height)
+						.take_while(|&row| self.is_white(row, col))
+						.map(|row| self.index(row, col).unwrap())
+						.collect();
+					entries.push(Entry {
+						number,
+						direction: Direction::Down,
+						start,
+						cells,
+					});
+				}
+			}
+		}
+
+		Numbering {
+			cell_numbers,
+			entries,
+		}
+	}
+}
+
+impl PuzFile {
+	/// Numbering of the puzzle, based on the blocks of the solution board.
+	/// The clues belong to this numbering.
+	pub fn numbering(&self) -> Numbering {
+		self.solution.numbering()
+	}
+
+	/// The board the player works with. In diagramless puzzles the player
+	/// places the blocks themselves, so it is the player state, otherwise it
+	/// is the solution.
+	pub fn diagram(&self) -> &Grid {
+		match self.puzzle_type {
+			PuzzleType::Diagramless => &self.player_state,
+			PuzzleType::Normal => &self.solution,
+		}
+	}
+
+	/// Numbering of the board as the player sees it, see `diagram`
+	pub fn player_numbering(&self) -> Numbering {
+		self.diagram().numbering()
+	}
+
+	/// Assigns the clues, which are stored as one flat list, to the entries
+	/// of the board.
+	pub fn numbered_clues(&self) -> Result<Vec<NumberedClue<'_>>, ClueCountMismatch> {
+		let entries = self.numbering().entries;
+
+		if entries.len() != self.clues.len() {
+			return Err(ClueCountMismatch {
+				entries: entries.len(),
+				clues: self.clues.len(),
+			});
+		}
+
+		Ok(entries
+			.into_iter()
+			.zip(&self.clues)
+			.map(|(entry, text)| NumberedClue { entry, text })
+			.collect())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn it_numbers_a_grid() {
+		let grid = Grid::from_bytes(4, 3, b"AB.CDEFG.HI.");
+
+		let numbering = grid.numbering();
+
+		assert_eq!(
+			numbering.cell_numbers,
+			[
+				Some(1),
+				Some(2),
+				None,
+				Some(3),
+				Some(4),
+				None,
+				Some(5),
+				None,
+				None,
+				Some(6),
+				None,
+				None
+			]
+		);
+
+		let summary: Vec<_> = numbering
+			.entries
+			.iter()
+			.map(|entry| (entry.number, entry.direction, entry.cells.clone()))
+			.collect();
+		assert_eq!(
+			summary,
+			[
+				(1, Direction::Across, vec![0, 1]),
+				(1, Direction::Down, vec![0, 4]),
+				(2, Direction::Down, vec![1, 5, 9]),
+				(3, Direction::Down, vec![3, 7]),
+				(4, Direction::Across, vec![4, 5, 6, 7]),
+				(5, Direction::Down, vec![6, 10]),
+				(6, Direction::Across, vec![9, 10]),
+			]
+		);
+	}
+
+	#[test]
+	fn it_numbers_the_players_diagram() {
+		let puzzle = include_bytes!("../fixtures/test-no-solution.puz");
+		let mut parsed = crate::parse_a_puz(puzzle).expect("Parsing Failed");
+		parsed.puzzle_type = PuzzleType::Diagramless;
+		parsed.player_state = Grid::from_bytes(3, 3, b"CA:------");
+
+		assert_eq!(parsed.numbering().entries.len(), 4);
+		assert_eq!(
+			parsed.player_numbering().cell_numbers,
+			[
+				Some(1),
+				Some(2),
+				None,
+				Some(3),
+				None,
+				Some(4),
+				Some(5),
+				None,
+				None
+			]
+		);
+	}
+
+	#[test]
+	fn it_assigns_clues_to_entries() {
+		let puzzle = include_bytes!("../fixtures/test-no-solution.puz");
+		let parsed = crate::parse_a_puz(puzzle).expect("Parsing Failed");
+
+		let clues = parsed.numbered_clues().unwrap();
+
+		let summary: Vec<_> = clues
+			.iter()
+			.map(|clue| (clue.entry.number, clue.entry.direction, clue.text))
+			.collect();
+		assert_eq!(
+			summary,
+			[
+				(1, Direction::Across, "Haustier 🐈"),
+				(1, Direction::Down, "Führerhaus (engl.)"),
+				(2, Direction::Down, "Zehe (engl.)"),
+				(3, Direction::Across, "Biene (engl.)"),
+			]
+		);
+	}
+}
@@ -0,0 +1,81 @@
+use crate::ParsePuzError;
+
+/// Reads the fields of a puz file one after another and remembers which
+/// field it is at, so errors can point to it
+pub(crate) struct FieldReader<'a> {
+	pub bytes: &'a [u8],
+	/// Start of the current field in `bytes`
+	pub position: usize,
+	/// Name of the current field
+	pub field: &'static str,
+}
+
+impl<'a> FieldReader<'a> {
+	pub fn new(bytes: &'a [u8], position: usize) -> Self {
+		Self {
+			bytes,
+			position,
+			field: "file checksum",
+		}
+	}
+
+	/// Moves to a field that is not read through the reader, e.g. to verify
+	/// a checksum
+	pub fn at(&mut self, position: usize, field: &'static str) {
+		self.position = position;
+		self.field = field;
+	}
+
+	/// The bytes after the current position
+	pub fn rest(&self) -> &'a [u8] {
+		&self.bytes[self.position.min(self.bytes.len())..]
+	}
+
+	pub fn bytes(&mut self, field: &'static str, length: usize) -> Result<&'a [u8], ParsePuzError> {
+		self.field = field;
+
+		let data = self
+			.bytes
+			.get(self.position..(self.position + length))
+			.ok_or(ParsePuzError::UnexpectedEnd {
+				expected: length,
+				found: self.rest().len(),
+			})?;
+		self.position += length;
+		Ok(data)
+	}
+
+	/// Reads a NUL-terminated string, without the terminator. Returns `None`
+	/// without advancing if there is no terminator.
+	pub fn string(&mut self, field: &'static str) -> Option<&'a [u8]> {
+		self.field = field;
+
+		let rest = self.rest();
+		let length = rest.iter().position(|&byte| byte == 0)?;
+		self.position += length + 1;
+		Some(&rest[..length])
+	}
+
+	pub fn array<const N: usize>(&mut self, field: &'static str) -> Result<[u8; N], ParsePuzError> {
+		Ok(self.bytes(field, N)?.try_into().unwrap())
+	}
+
+	pub fn u8(&mut self, field: &'static str) -> Result<u8, ParsePuzError> {
+		Ok(self.bytes(field, 1)?[0])
+	}
+
+	/// Reads a little endian u16
+	pub fn u16(&mut self, field: &'static str) -> Result<u16, ParsePuzError> {
+		Ok(u16::from_le_bytes(self.array(field)?))
+	}
+
+	/// Reads a little endian u32
+	pub fn u32(&mut self, field: &'static str) -> Result<u32, ParsePuzError> {
+		Ok(u32::from_le_bytes(self.array(field)?))
+	}
+
+	/// Reads a little endian u64
+	pub fn u64(&mut self, field: &'static str) -> Result<u64, ParsePuzError> {
+		Ok(u64::from_le_bytes(self.array(field)?))
+	}
+}
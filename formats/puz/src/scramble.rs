@@ -0,0 +1,351 @@
+use crate::{Cell, Crc16Checksum, PuzFile, SolutionType};
+use alloc::vec::Vec;
+use core::ops::Range;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use thiserror::Error;
+
+/// Number of possible scramble keys, 0000 - 9999
+const KEY_COUNT: u16 = 10000;
+
+/// Number of keys a search thread tries before reporting progress
+const KEYS_PER_PROGRESS_REPORT: u16 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeySearchProgress {
+	/// Number of keys tried so far
+	pub checked: usize,
+	/// Number of keys that will be tried in total
+	pub total: usize,
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum ScrambleError {
+	#[error("the solution of this puzzle is not scrambled")]
+	NotScrambled,
+	#[error("the solution of this puzzle is already scrambled")]
+	AlreadyScrambled,
+	#[error("this puzzle does not contain a solution")]
+	MissingSolution,
+	#[error("scramble keys have four digits, {0} is too large")]
+	InvalidKey(u16),
+	#[error("only solutions consisting of the letters A-Z can be scrambled, found '{0}'")]
+	UnscramblableCell(char),
+	#[error("the key does not unlock the solution")]
+	WrongKey,
+}
+
+/// The four digits of a key, with leading zeros
+fn key_digits(key: u16) -> Result<[u8; 4], ScrambleError> {
+	if key > 9999 {
+		return Err(ScrambleError::InvalidKey(key));
+	}
+
+	Ok([
+		(key / 1000) as u8,
+		(key / 100 % 10) as u8,
+		(key / 10 % 10) as u8,
+		(key % 10) as u8,
+	])
+}
+
+fn shift(letters: &mut [u8], digits: [u8; 4], backwards: bool) {
+	for (index, letter) in letters.iter_mut().enumerate() {
+		let digit = digits[index % 4];
+		let digit = if backwards { 26 - digit } else { digit };
+		*letter = b'A' + (*letter - b'A' + digit) % 26;
+	}
+}
+
+/// Interleaves the second half with the first half, like riffling a deck of
+/// cards. With an odd length, the last letter stays in place.
+fn shuffle(letters: &[u8]) -> Vec<u8> {
+	let middle = letters.len() / 2;
+	let mut shuffled: Vec<u8> = letters[middle..]
+		.iter()
+		.zip(&letters[..middle])
+		.flat_map(|(&second, &first)| [second, first])
+		.collect();
+	if letters.len() % 2 == 1 {
+		shuffled.push(letters[letters.len() - 1]);
+	}
+	shuffled
+}
+
+fn unshuffle(letters: &[u8]) -> Vec<u8> {
+	let odd = letters.iter().skip(1).step_by(2);
+	let even = letters.iter().step_by(2);
+	odd.chain(even).copied().collect()
+}
+
+pub(crate) fn scramble_letters(mut letters: Vec<u8>, digits: [u8; 4]) -> Vec<u8> {
+	for digit in digits {
+		shift(&mut letters, digits, false);
+		if (digit as usize) <= letters.len() {
+			letters.rotate_left(digit as usize);
+		}
+		letters = shuffle(&letters);
+	}
+	letters
+}
+
+pub(crate) fn unscramble_letters(mut letters: Vec<u8>, digits: [u8; 4]) -> Vec<u8> {
+	for digit in digits.into_iter().rev() {
+		letters = unshuffle(&letters);
+		if (digit as usize) <= letters.len() {
+			letters.rotate_right(digit as usize);
+		}
+		shift(&mut letters, digits, true);
+	}
+	letters
+}
+
+/// Indices of the white squares of the solution, column by column.
+/// This is the order the scrambling works in.
+fn scramble_order(puz: &PuzFile) -> Vec<usize> {
+	let solution = &puz.solution;
+	(0..solution.width)
+		.flat_map(|col| (0..solution.height).map(move |row| (row, col)))
+		.filter_map(|(row, col)| solution.index(row, col))
+		.filter(|&index| !solution.cells[index].is_block())
+		.collect()
+}
+
+/// The letters of the solution in scrambling order
+pub(crate) fn scramble_input(puz: &PuzFile) -> Result<(Vec<usize>, Vec<u8>), ScrambleError> {
+	let order = scramble_order(puz);
+	let letters = order
+		.iter()
+		.map(|&index| match puz.solution.cells[index] {
+			Cell::Letter(letter @ b'A'..=b'Z') => Ok(letter),
+			cell => Err(ScrambleError::UnscramblableCell(u8::from(cell) as char)),
+		})
+		.collect::<Result<_, _>>()?;
+
+	Ok((order, letters))
+}
+
+impl PuzFile {
+	/// Unlocks a scrambled solution with the four-digit key. The key is
+	/// verified with the checksum of the real solution stored in the file.
+	pub fn unscramble(&mut self, key: u16) -> Result<(), ScrambleError> {
+		match self.solution_type {
+			SolutionType::Scrambled => {}
+			SolutionType::Missing => return Err(ScrambleError::MissingSolution),
+			SolutionType::Normal => return Err(ScrambleError::NotScrambled),
+		}
+
+		let digits = key_digits(key)?;
+		let (order, letters) = scramble_input(self)?;
+		let letters = unscramble_letters(letters, digits);
+
+		if Some(Crc16Checksum::of(&letters)) != self.checksum_scrambled {
+			return Err(ScrambleError::WrongKey);
+		}
+
+		for (index, letter) in order.into_iter().zip(letters) {
+			self.solution.cells[index] = Cell::Letter(letter);
+		}
+		self.solution_type = SolutionType::Normal;
+		self.checksum_scrambled = None;
+
+		Ok(())
+	}
+
+	/// Locks the solution with a four-digit key (leading zeros are part of
+	/// the key), so solvers can not check or reveal their answers.
+	pub fn scramble(&mut self, key: u16) -> Result<(), ScrambleError> {
+		match self.solution_type {
+			SolutionType::Normal => {}
+			SolutionType::Missing => return Err(ScrambleError::MissingSolution),
+			SolutionType::Scrambled => return Err(ScrambleError::AlreadyScrambled),
+		}
+
+		let digits = key_digits(key)?;
+		let (order, letters) = scramble_input(self)?;
+		let checksum = Crc16Checksum::of(&letters);
+		let letters = scramble_letters(letters, digits);
+
+		for (index, letter) in order.into_iter().zip(letters) {
+			self.solution.cells[index] = Cell::Letter(letter);
+		}
+		self.solution_type = SolutionType::Scrambled;
+		self.checksum_scrambled = Some(checksum);
+
+		Ok(())
+	}
+
+	/// Finds the keys that unlock a scrambled solution, see
+	/// `find_scramble_keys_with_progress`.
+	pub fn find_scramble_keys(&self) -> Result<Vec<u16>, ScrambleError> {
+		self.find_scramble_keys_with_progress(|_| {})
+	}
+
+	/// Tries all 10,000 keys on a scrambled solution, spread over all
+	/// available cores with the `std` feature, and returns the ones that pass
+	/// the checksum of the real solution, in ascending order.
+	///
+	/// The checksum only has 16 bits, so especially for small puzzles more
+	/// than one key may be found. All of them are accepted by `unscramble`,
+	/// but only one of them gives the real solution.
+	///
+	/// `on_progress` is called from the search threads every now and then.
+	pub fn find_scramble_keys_with_progress<F>(
+		&self,
+		on_progress: F,
+	) -> Result<Vec<u16>, ScrambleError>
+	where
+		F: Fn(KeySearchProgress) + Sync,
+	{
+		match self.solution_type {
+			SolutionType::Scrambled => {}
+			SolutionType::Missing => return Err(ScrambleError::MissingSolution),
+			SolutionType::Normal => return Err(ScrambleError::NotScrambled),
+		}
+
+		let (_, letters) = scramble_input(self)?;
+		let expected = self.checksum_scrambled;
+
+		let checked = AtomicUsize::new(0);
+
+		#[cfg(feature = "std")]
+		let mut found = {
+			let threads =
+				std::thread::available_parallelism().map_or(1, |threads| threads.get()) as u16;
+			let keys_per_thread = KEY_COUNT.div_ceil(threads);
+			let found = std::sync::Mutex::new(Vec::new());
+
+			std::thread::scope(|scope| {
+				for first_key in (0..KEY_COUNT).step_by(keys_per_thread as usize) {
+					let last_key = (first_key + keys_per_thread).min(KEY_COUNT);
+					let (letters, checked, found, on_progress) =
+						(&letters, &checked, &found, &on_progress);
+
+					scope.spawn(move || {
+						search_keys(letters, expected, first_key..last_key, checked, on_progress)
+							.for_each(|key| found.lock().unwrap().push(key));
+					});
+				}
+			});
+
+			found.into_inner().unwrap()
+		};
+
+		#[cfg(not(feature = "std"))]
+		let mut found: Vec<_> =
+			search_keys(&letters, expected, 0..KEY_COUNT, &checked, &on_progress).collect();
+
+		found.sort_unstable();
+		Ok(found)
+	}
+}
+
+/// Tries the given keys and yields the ones that pass the checksum, reporting
+/// progress after every batch
+fn search_keys<'a, F>(
+	letters: &'a [u8],
+	expected: Option<Crc16Checksum>,
+	keys: Range<u16>,
+	checked: &'a AtomicUsize,
+	on_progress: &'a F,
+) -> impl Iterator<Item = u16> + 'a
+where
+	F: Fn(KeySearchProgress),
+{
+	let last_key = keys.end;
+	keys.step_by(KEYS_PER_PROGRESS_REPORT as usize)
+		.flat_map(move |batch_start| {
+			let batch_end = (batch_start + KEYS_PER_PROGRESS_REPORT).min(last_key);
+
+			let found: Vec<_> = (batch_start..batch_end)
+				.filter(|&key| {
+					let unscrambled =
+						unscramble_letters(letters.to_vec(), key_digits(key).unwrap());
+					Some(Crc16Checksum::of(&unscrambled)) == expected
+				})
+				.collect();
+
+			let batch_size = (batch_end - batch_start) as usize;
+			on_progress(KeySearchProgress {
+				checked: checked.fetch_add(batch_size, Ordering::Relaxed) + batch_size,
+				total: KEY_COUNT as usize,
+			});
+			found
+		})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::parse_a_puz;
+	use std::sync::Mutex;
+
+	#[test]
+	fn it_scrambles_letters() {
+		let scrambled = scramble_letters(b"ABCDEFGHIJKLMNOPQRSTUVWXYZABC".to_vec(), [7, 8, 4, 4]);
+
+		assert_eq!(scrambled, b"PHZRGZWFWTEGDJIYINARIZPLHUJGU");
+		assert_eq!(
+			unscramble_letters(scrambled, [7, 8, 4, 4]),
+			b"ABCDEFGHIJKLMNOPQRSTUVWXYZABC"
+		);
+	}
+
+	#[test]
+	fn it_scrambles_and_unscrambles_puzzles() {
+		let puzzle = include_bytes!("../fixtures/test-extensions.puz");
+		let original = parse_a_puz(puzzle).expect("Parsing Failed");
+		let mut puz = original.clone();
+
+		puz.scramble(1234).unwrap();
+
+		assert_eq!(puz.solution_type, SolutionType::Scrambled);
+		assert_eq!(puz.checksum_scrambled, Some(Crc16Checksum(0x52a2)));
+		assert_eq!(puz.solution.to_bytes(), b"FCCX.VNAP");
+		assert_eq!(puz.scramble(1234), Err(ScrambleError::AlreadyScrambled));
+
+		let mut reparsed = parse_a_puz(&puz.to_bytes().unwrap()).expect("Parsing Failed");
+		assert_eq!(reparsed.unscramble(4321), Err(ScrambleError::WrongKey));
+		reparsed.unscramble(1234).unwrap();
+
+		assert_eq!(reparsed.solution, original.solution);
+		assert_eq!(reparsed.solution_type, SolutionType::Normal);
+		assert_eq!(reparsed.checksum_scrambled, None);
+	}
+
+	#[test]
+	fn it_finds_scramble_keys() {
+		let puzzle = include_bytes!("../fixtures/test-extensions.puz");
+		let original = parse_a_puz(puzzle).expect("Parsing Failed");
+		let mut puz = original.clone();
+		puz.scramble(7844).unwrap();
+
+		let progress = Mutex::new(Vec::new());
+		let keys = puz
+			.find_scramble_keys_with_progress(|update| progress.lock().unwrap().push(update))
+			.unwrap();
+
+		assert!(keys.contains(&7844));
+		for key in keys {
+			let mut unlocked = puz.clone();
+			assert_eq!(unlocked.unscramble(key), Ok(()));
+		}
+
+		let progress = progress.into_inner().unwrap();
+		let last = progress.iter().max_by_key(|update| update.checked).unwrap();
+		assert_eq!(last.checked, 10000);
+		assert_eq!(last.total, 10000);
+
+		assert_eq!(
+			original.find_scramble_keys(),
+			Err(ScrambleError::NotScrambled)
+		);
+	}
+
+	#[test]
+	fn it_rejects_invalid_keys() {
+		let puzzle = include_bytes!("../fixtures/test-extensions.puz");
+		let mut puz = parse_a_puz(puzzle).expect("Parsing Failed");
+
+		assert_eq!(puz.scramble(10000), Err(ScrambleError::InvalidKey(10000)));
+	}
+}
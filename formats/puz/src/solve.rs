@@ -0,0 +1,789 @@
+use crate::history::{HistoryError, Operation, OperationKind, SquareChange, SquareState};
+use crate::{
+	Annotation, Cell, CellFlags, Direction, Highlight, History, Numbering, PuzFile, PuzzleType,
+	SolutionType,
+};
+use alloc::{borrow::ToOwned, string::String, vec, vec::Vec};
+use core::time::Duration;
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum SolveError {
+	#[error("this puzzle does not contain a solution")]
+	MissingSolution,
+	#[error("the solution of this puzzle is scrambled and needs to be unscrambled first")]
+	ScrambledSolution,
+	#[error("there is no square at index {0}")]
+	NoSquare(usize),
+	#[error("the square at index {index} is not part of a {direction:?} entry")]
+	NoEntry { index: usize, direction: Direction },
+	#[error("blocks can only be placed in diagramless puzzles")]
+	NotDiagramless,
+	#[error("the square at index {0} is a block")]
+	NotWhite(usize),
+	#[error("0x{0:02x} can not be entered, it is not a letter in puz grids")]
+	InvalidLetter(u8),
+	#[error("the square at index {0} already has the maximum number of candidates")]
+	TooManyCandidates(usize),
+}
+
+/// The cell for an entered letter. The bytes for blocks and empty squares
+/// would change the square when written, and NUL ends the grid.
+pub(crate) fn letter_cell(letter: u8) -> Result<Cell, SolveError> {
+	match Cell::from(letter) {
+		Cell::Letter(0) | Cell::Block | Cell::DiagramlessBlock | Cell::Empty => {
+			Err(SolveError::InvalidLetter(letter))
+		}
+		cell => Ok(cell),
+	}
+}
+
+/// The squares an operation applies to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+	Square(usize),
+	/// The entry in the given direction containing the square
+	Word(usize, Direction),
+	Puzzle,
+}
+
+#[cfg(feature = "std")]
+fn system_time() -> Duration {
+	std::time::SystemTime::now()
+		.duration_since(std::time::UNIX_EPOCH)
+		.unwrap_or_default()
+}
+
+/// Without a system clock every operation happens at 0, so typing is always
+/// coalesced until a clock is set
+#[cfg(not(feature = "std"))]
+fn system_time() -> Duration {
+	Duration::ZERO
+}
+
+/// Solving state on top of a puzzle. Entering, checking, revealing and
+/// clearing squares changes the player state, the user rebus entries and the
+/// cell flags of the puzzle like Across Lite does.
+///
+/// In diagramless puzzles the player also places the blocks. The words are
+/// then taken from the player's diagram and checking includes the blocks.
+///
+/// Pencil marks, candidates and highlights are kept in the annotations of the
+/// puzzle. Every change is recorded in a `History` and can be undone.
+#[derive(Debug, Clone)]
+pub struct SolveSession {
+	puz: PuzFile,
+	numbering: Numbering,
+	history: History,
+	clock: fn() -> Duration,
+}
+
+impl SolveSession {
+	pub fn new(puz: PuzFile) -> Self {
+		let numbering = puz.player_numbering();
+		Self {
+			puz,
+			numbering,
+			history: History::new(),
+			clock: system_time,
+		}
+	}
+
+	/// Resumes a session with the history of an earlier session on the same
+	/// puzzle
+	pub fn with_history(puz: PuzFile, history: History) -> Result<Self, HistoryError> {
+		let size = puz.player_state.cells.len();
+		if let Some(index) = history.max_index().filter(|&index| index >= size) {
+			return Err(HistoryError::SquareOutOfRange { index, size });
+		}
+
+		Ok(Self {
+			history,
+			..Self::new(puz)
+		})
+	}
+
+	/// Replaces the source of the operation times, which defaults to the
+	/// system time (or always 0 without the `std` feature)
+	pub fn set_clock(&mut self, clock: fn() -> Duration) {
+		self.clock = clock;
+	}
+
+	fn is_diagramless(&self) -> bool {
+		self.puz.puzzle_type == PuzzleType::Diagramless
+	}
+
+	/// The blocks of diagramless puzzles can change with every operation
+	pub(crate) fn update_numbering(&mut self) {
+		if self.is_diagramless() {
+			self.numbering = self.puz.player_numbering();
+		}
+	}
+
+	pub fn puz(&self) -> &PuzFile {
+		&self.puz
+	}
+
+	/// The puzzle without recording changes, for state kept elsewhere
+	pub(crate) fn puz_mut(&mut self) -> &mut PuzFile {
+		&mut self.puz
+	}
+
+	pub fn into_puz(self) -> PuzFile {
+		self.puz
+	}
+
+	pub fn numbering(&self) -> &Numbering {
+		&self.numbering
+	}
+
+	pub fn history(&self) -> &History {
+		&self.history
+	}
+
+	pub fn history_mut(&mut self) -> &mut History {
+		&mut self.history
+	}
+
+	/// The pencil mark, candidates and highlight of the square
+	pub fn annotation(&self, index: usize) -> Option<&Annotation> {
+		self.puz.annotations.as_ref()?.get(index)
+	}
+
+	/// Whether the square was filled in with a pencil
+	pub fn is_pencil(&self, index: usize) -> bool {
+		self.annotation(index)
+			.is_some_and(|annotation| annotation.pencil)
+	}
+
+	/// The squares of the given scope the player can change: the white squares,
+	/// or all squares in diagramless puzzles
+	pub fn squares(&self, scope: Scope) -> Result<Vec<usize>, SolveError> {
+		let is_white =
+			|&index: &usize| self.is_diagramless() || !self.puz.solution.cells[index].is_block();
+
+		match scope {
+			Scope::Square(index) if index < self.puz.solution.cells.len() => {
+				Ok(Some(index).into_iter().filter(is_white).collect())
+			}
+			Scope::Square(index) => Err(SolveError::NoSquare(index)),
+			Scope::Word(index, direction) => self
+				.numbering
+				.entry_at(index, direction)
+				.map(|entry| entry.cells.clone())
+				.ok_or(SolveError::NoEntry { index, direction }),
+			Scope::Puzzle => Ok((0..self.puz.solution.cells.len())
+				.filter(is_white)
+				.collect()),
+		}
+	}
+
+	/// The square at the index as a scope for entering letters, which fails
+	/// for blocks
+	pub(crate) fn letter_square(&self, index: usize) -> Result<Vec<usize>, SolveError> {
+		let squares = self.squares(Scope::Square(index))?;
+		if squares.is_empty() {
+			return Err(SolveError::NotWhite(index));
+		}
+		Ok(squares)
+	}
+
+	pub(crate) fn require_solution(&self) -> Result<(), SolveError> {
+		match self.puz.solution_type {
+			SolutionType::Normal => Ok(()),
+			SolutionType::Missing => Err(SolveError::MissingSolution),
+			SolutionType::Scrambled => Err(SolveError::ScrambledSolution),
+		}
+	}
+
+	fn flags_mut(&mut self, index: usize) -> &mut CellFlags {
+		let size = self.puz.player_state.cells.len();
+		let flags = self
+			.puz
+			.cell_flags
+			.get_or_insert_with(|| vec![CellFlags::empty(); size]);
+		&mut flags[index]
+	}
+
+	fn flags(&self, index: usize) -> CellFlags {
+		self.puz
+			.cell_flags
+			.as_ref()
+			.and_then(|flags| flags.get(index).copied())
+			.unwrap_or_default()
+	}
+
+	fn annotation_mut(&mut self, index: usize) -> &mut Annotation {
+		let size = self.puz.player_state.cells.len();
+		let annotations = self
+			.puz
+			.annotations
+			.get_or_insert_with(|| vec![Annotation::default(); size]);
+		&mut annotations[index]
+	}
+
+	fn set_pencil(&mut self, index: usize, pencil: bool) {
+		if self.is_pencil(index) != pencil {
+			self.annotation_mut(index).pencil = pencil;
+		}
+	}
+
+	fn user_rebus(&self, index: usize) -> Option<&str> {
+		self.puz.user_rebus.as_ref()?.get(index)?.as_deref()
+	}
+
+	fn set_user_rebus(&mut self, index: usize, rebus: Option<String>) {
+		if rebus.is_none() && self.puz.user_rebus.is_none() {
+			return;
+		}
+
+		let size = self.puz.player_state.cells.len();
+		self.puz.user_rebus.get_or_insert_with(|| vec![None; size])[index] = rebus;
+	}
+
+	fn square_state(&self, index: usize) -> SquareState {
+		SquareState {
+			cell: self.puz.player_state.cells[index],
+			rebus: self.user_rebus(index).map(str::to_owned),
+			flags: self.flags(index),
+			annotation: self.annotation(index).cloned().unwrap_or_default(),
+		}
+	}
+
+	fn restore_square_state(&mut self, index: usize, state: &SquareState) {
+		self.puz.player_state.cells[index] = state.cell;
+		self.set_user_rebus(index, state.rebus.clone());
+		if self.flags(index) != state.flags {
+			*self.flags_mut(index) = state.flags;
+		}
+		if self.annotation(index).cloned().unwrap_or_default() != state.annotation {
+			*self.annotation_mut(index) = state.annotation.clone();
+		}
+	}
+
+	/// Applies a change to the squares and records it in the history
+	fn record<T>(
+		&mut self,
+		kind: OperationKind,
+		squares: Vec<usize>,
+		apply: impl FnOnce(&mut Self, &[usize]) -> T,
+	) -> T {
+		let before: Vec<SquareState> = squares
+			.iter()
+			.map(|&index| self.square_state(index))
+			.collect();
+
+		let result = apply(self, &squares);
+		self.update_numbering();
+
+		let changes = squares
+			.into_iter()
+			.zip(before)
+			.filter_map(|(index, before)| {
+				let after = self.square_state(index);
+				(before != after).then_some(SquareChange {
+					index,
+					before,
+					after,
+				})
+			})
+			.collect();
+
+		self.history.push(Operation {
+			kind,
+			at: (self.clock)(),
+			changes,
+		});
+
+		result
+	}
+
+	/// Reverts the last operation and returns its kind, if there is one
+	pub fn undo(&mut self) -> Option<OperationKind> {
+		let operation = self.history.undo()?.clone();
+		for change in operation.changes.iter().rev() {
+			self.restore_square_state(change.index, &change.before);
+		}
+		self.update_numbering();
+		Some(operation.kind)
+	}
+
+	/// Applies the last undone operation again and returns its kind, if there
+	/// is one
+	pub fn redo(&mut self) -> Option<OperationKind> {
+		let operation = self.history.redo()?.clone();
+		for change in &operation.changes {
+			self.restore_square_state(change.index, &change.after);
+		}
+		self.update_numbering();
+		Some(operation.kind)
+	}
+
+	pub(crate) fn is_filled(&self, index: usize) -> bool {
+		match self.puz.player_state.cells[index] {
+			Cell::Letter(_) => true,
+			cell => cell.is_block(),
+		}
+	}
+
+	pub(crate) fn is_correct(&self, index: usize) -> bool {
+		let solution_is_block = self.puz.solution.cells[index].is_block();
+		let player_is_block = self.puz.player_state.cells[index].is_block();
+		if solution_is_block || player_is_block {
+			return solution_is_block && player_is_block;
+		}
+
+		let solution_rebus = self
+			.puz
+			.rebus
+			.as_ref()
+			.and_then(|rebus| rebus.solution_at(index));
+
+		match (solution_rebus, self.user_rebus(index)) {
+			(Some(solution), Some(entered)) => solution.eq_ignore_ascii_case(entered),
+			(Some(_), None) => false,
+			(None, _) => match (
+				self.puz.solution.cells[index],
+				self.puz.player_state.cells[index],
+			) {
+				(Cell::Letter(solution), Cell::Letter(entered)) => {
+					solution.eq_ignore_ascii_case(&entered)
+				}
+				_ => false,
+			},
+		}
+	}
+
+	/// Changes the contents of a square. A square that is currently marked
+	/// incorrect is marked as previously incorrect instead.
+	fn set_square(&mut self, index: usize, cell: Cell, rebus: Option<String>) {
+		self.puz.player_state.cells[index] = cell;
+		self.set_user_rebus(index, rebus);
+		self.set_pencil(index, false);
+
+		if self.flags(index).contains(CellFlags::INCORRECT) {
+			let flags = self.flags_mut(index);
+			flags.remove(CellFlags::INCORRECT);
+			flags.insert(CellFlags::PREVIOUSLY_INCORRECT);
+		}
+	}
+
+	/// Enters a letter into a white square
+	pub fn enter_letter(&mut self, index: usize, letter: u8) -> Result<(), SolveError> {
+		let cell = letter_cell(letter)?;
+		let squares = self.letter_square(index)?;
+		self.record(OperationKind::Letter, squares, |session, squares| {
+			for &index in squares {
+				session.set_square(index, cell, None);
+			}
+		});
+		Ok(())
+	}
+
+	/// Enters a tentative letter into a white square
+	pub fn enter_pencil(&mut self, index: usize, letter: u8) -> Result<(), SolveError> {
+		let cell = letter_cell(letter)?;
+		let squares = self.letter_square(index)?;
+		self.record(OperationKind::Pencil, squares, |session, squares| {
+			for &index in squares {
+				session.set_square(index, cell, None);
+				session.set_pencil(index, true);
+			}
+		});
+		Ok(())
+	}
+
+	/// Adds a candidate letter to a white square, or removes it if the square
+	/// already has it. A square has at most `Annotation::MAX_CANDIDATES`.
+	pub fn toggle_candidate(&mut self, index: usize, letter: u8) -> Result<(), SolveError> {
+		let squares = self.squares(Scope::Square(index))?;
+		if let Some(annotation) = self.annotation(index) {
+			if annotation.candidates.len() >= Annotation::MAX_CANDIDATES
+				&& !annotation.candidates.contains(&letter)
+			{
+				return Err(SolveError::TooManyCandidates(index));
+			}
+		}
+		self.record(OperationKind::Candidate, squares, |session, squares| {
+			for &index in squares {
+				let candidates = &mut session.annotation_mut(index).candidates;
+				match candidates.iter().position(|&candidate| candidate == letter) {
+					Some(position) => {
+						candidates.remove(position);
+					}
+					None => candidates.push(letter),
+				}
+			}
+		});
+		Ok(())
+	}
+
+	/// Highlights the squares of the scope, or removes their highlight
+	pub fn set_highlight(
+		&mut self,
+		scope: Scope,
+		highlight: Option<Highlight>,
+	) -> Result<(), SolveError> {
+		let squares = self.squares(scope)?;
+		self.record(OperationKind::Highlight, squares, |session, squares| {
+			for &index in squares {
+				if session
+					.annotation(index)
+					.and_then(|annotation| annotation.highlight)
+					!= highlight
+				{
+					session.annotation_mut(index).highlight = highlight;
+				}
+			}
+		});
+		Ok(())
+	}
+
+	/// Places a block on an open square or opens a blocked square of a
+	/// diagramless puzzle
+	pub fn toggle_block(&mut self, index: usize) -> Result<(), SolveError> {
+		if !self.is_diagramless() {
+			return Err(SolveError::NotDiagramless);
+		}
+
+		let squares = self.squares(Scope::Square(index))?;
+		self.record(OperationKind::ToggleBlock, squares, |session, squares| {
+			for &index in squares {
+				let cell = if session.puz.player_state.cells[index].is_block() {
+					Cell::Empty
+				} else {
+					Cell::DiagramlessBlock
+				};
+				session.set_square(index, cell, None);
+			}
+		});
+		Ok(())
+	}
+
+	/// Enters multiple letters into a white square
+	pub fn enter_rebus(&mut self, index: usize, rebus: &str) -> Result<(), SolveError> {
+		let Some(first) = rebus.bytes().next() else {
+			return self.clear(Scope::Square(index));
+		};
+
+		let cell = letter_cell(first)?;
+		let squares = self.letter_square(index)?;
+		self.record(OperationKind::Rebus, squares, |session, squares| {
+			for &index in squares {
+				session.set_square(index, cell, Some(rebus.to_owned()));
+			}
+		});
+		Ok(())
+	}
+
+	/// Marks the filled, but wrong squares of the scope as incorrect and
+	/// returns them. Empty squares are not marked.
+	pub fn check(&mut self, scope: Scope) -> Result<Vec<usize>, SolveError> {
+		self.require_solution()?;
+
+		let squares = self.squares(scope)?;
+		Ok(
+			self.record(OperationKind::Check, squares, |session, squares| {
+				let incorrect: Vec<usize> = squares
+					.iter()
+					.copied()
+					.filter(|&index| session.is_filled(index) && !session.is_correct(index))
+					.collect();
+
+				for &index in &incorrect {
+					session.flags_mut(index).insert(CellFlags::INCORRECT);
+				}
+
+				incorrect
+			}),
+		)
+	}
+
+	/// Fills the solution into the squares of the scope that are not correct
+	/// yet and marks them as revealed. Wrong letters that get replaced are
+	/// marked as previously incorrect.
+	pub fn reveal(&mut self, scope: Scope) -> Result<(), SolveError> {
+		self.require_solution()?;
+
+		let squares = self.squares(scope)?;
+		self.record(OperationKind::Reveal, squares, |session, squares| {
+			for &index in squares {
+				if session.is_correct(index) {
+					continue;
+				}
+
+				let was_wrong = session.is_filled(index);
+				let rebus = session
+					.puz
+					.rebus
+					.as_ref()
+					.and_then(|rebus| rebus.solution_at(index))
+					.map(str::to_owned);
+				session.puz.player_state.cells[index] = session.puz.solution.cells[index];
+				session.set_user_rebus(index, rebus);
+				session.set_pencil(index, false);
+
+				let flags = session.flags_mut(index);
+				flags.remove(CellFlags::INCORRECT);
+				flags.insert(CellFlags::REVEALED);
+				if was_wrong {
+					flags.insert(CellFlags::PREVIOUSLY_INCORRECT);
+				}
+			}
+		});
+		Ok(())
+	}
+
+	/// Empties the squares of the scope. The incorrect and revealed marks are
+	/// removed, circles and the previously incorrect marks are kept.
+	pub fn clear(&mut self, scope: Scope) -> Result<(), SolveError> {
+		let squares = self.squares(scope)?;
+		self.record(OperationKind::Clear, squares, |session, squares| {
+			for &index in squares {
+				session.puz.player_state.cells[index] = Cell::Empty;
+				session.set_user_rebus(index, None);
+				session.set_pencil(index, false);
+
+				if !session.flags(index).is_empty() {
+					let flags = session.flags_mut(index);
+					flags.remove(CellFlags::INCORRECT);
+					flags.remove(CellFlags::REVEALED);
+				}
+			}
+		});
+		Ok(())
+	}
+
+	/// Whether every white square is filled in correctly
+	pub fn is_solved(&self) -> Result<bool, SolveError> {
+		self.require_solution()?;
+
+		Ok(self
+			.squares(Scope::Puzzle)?
+			.into_iter()
+			.all(|index| self.is_correct(index)))
+	}
+}
+
+/// The puzzle the solving tests start from: the extensions fixture, without
+/// its cell flags
+#[cfg(test)]
+pub(crate) fn test_puzzle() -> PuzFile {
+	let puzzle = include_bytes!("../fixtures/test-extensions.puz");
+	let mut puz = crate::parse_a_puz(puzzle).expect("Parsing Failed");
+	puz.cell_flags = None;
+	puz
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{parse_a_puz, Grid, WritePuzError};
+
+	fn session() -> SolveSession {
+		SolveSession::new(test_puzzle())
+	}
+
+	#[test]
+	fn it_rejects_what_can_not_be_entered() {
+		let mut session = session();
+
+		for letter in [b'.', b'-', b':', 0] {
+			assert_eq!(
+				session.enter_letter(0, letter),
+				Err(SolveError::InvalidLetter(letter))
+			);
+		}
+		assert_eq!(
+			session.enter_rebus(0, ".A"),
+			Err(SolveError::InvalidLetter(b'.'))
+		);
+		assert_eq!(session.enter_letter(4, b'A'), Err(SolveError::NotWhite(4)));
+		assert_eq!(session.enter_pencil(4, b'A'), Err(SolveError::NotWhite(4)));
+		assert!(!session.history().can_undo());
+	}
+
+	#[test]
+	fn it_checks_squares() {
+		let mut session = session();
+
+		assert_eq!(session.check(Scope::Square(1)), Ok(vec![]));
+		assert_eq!(session.check(Scope::Word(0, Direction::Down)), Ok(vec![3]));
+		assert_eq!(session.check(Scope::Puzzle), Ok(vec![3, 7]));
+
+		let flags = session.puz().cell_flags.clone().unwrap();
+		assert!(flags[3].contains(CellFlags::INCORRECT));
+		assert!(flags[7].contains(CellFlags::INCORRECT));
+		assert!(flags[1].is_empty());
+
+		session.enter_letter(3, b'E').unwrap();
+
+		let flags = session.puz().cell_flags.clone().unwrap();
+		assert!(!flags[3].contains(CellFlags::INCORRECT));
+		assert!(flags[3].contains(CellFlags::PREVIOUSLY_INCORRECT));
+	}
+
+	#[test]
+	fn it_reveals_squares() {
+		let mut session = session();
+
+		session.reveal(Scope::Word(8, Direction::Down)).unwrap();
+
+		let puz = session.puz();
+		assert_eq!(puz.player_state.to_bytes(), b"SUNN.OATT");
+		assert_eq!(puz.user_rebus.as_ref().unwrap()[8].as_deref(), Some("TEA"));
+		let flags = puz.cell_flags.as_ref().unwrap();
+		assert!(flags[5].contains(CellFlags::REVEALED));
+		assert!(!flags[5].contains(CellFlags::PREVIOUSLY_INCORRECT));
+		assert!(flags[8].contains(CellFlags::REVEALED));
+		assert!(flags[1].is_empty());
+
+		session.reveal(Scope::Puzzle).unwrap();
+
+		assert_eq!(session.is_solved(), Ok(true));
+		let flags = session.puz().cell_flags.as_ref().unwrap();
+		assert!(flags[7].contains(CellFlags::REVEALED | CellFlags::PREVIOUSLY_INCORRECT));
+		assert!(flags[0].is_empty());
+	}
+
+	#[test]
+	fn it_clears_squares() {
+		let mut session = session();
+		session.check(Scope::Puzzle).unwrap();
+
+		session.clear(Scope::Word(0, Direction::Across)).unwrap();
+		assert_eq!(session.puz().player_state.to_bytes(), b"---N.-AT-");
+		assert_eq!(session.puz().user_rebus.as_ref().unwrap()[0], None);
+
+		session.clear(Scope::Puzzle).unwrap();
+		assert_eq!(session.puz().player_state.to_bytes(), b"----.----");
+		assert!(session
+			.puz()
+			.cell_flags
+			.as_ref()
+			.unwrap()
+			.iter()
+			.all(CellFlags::is_empty));
+	}
+
+	#[test]
+	fn it_annotates_squares() {
+		let mut session = session();
+		let yellow = Highlight {
+			red: 0xff,
+			green: 0xee,
+			blue: 0x00,
+		};
+
+		session.enter_pencil(2, b'M').unwrap();
+		session.toggle_candidate(5, b'O').unwrap();
+		session.toggle_candidate(5, b'U').unwrap();
+		session.toggle_candidate(5, b'O').unwrap();
+		session
+			.set_highlight(Scope::Word(0, Direction::Across), Some(yellow))
+			.unwrap();
+
+		let bytes = session.into_puz().to_bytes().unwrap();
+		let reparsed = parse_a_puz(&bytes).expect("Parsing Failed");
+		assert_eq!(reparsed.garbage.section_order.last(), Some(b"ANNO"));
+
+		let annotations = reparsed.annotations.clone().unwrap();
+		assert!(annotations[2].pencil);
+		assert_eq!(annotations[5].candidates, b"U");
+		assert_eq!(annotations[1].highlight, Some(yellow));
+		assert!(annotations[8].is_empty());
+
+		let mut session = SolveSession::new(reparsed);
+		session.enter_letter(2, b'N').unwrap();
+		assert!(!session.is_pencil(2));
+		session.undo();
+		assert!(session.is_pencil(2));
+		assert_eq!(session.puz().player_state.cells[2], Cell::Letter(b'M'));
+	}
+
+	#[test]
+	fn it_limits_the_number_of_candidates() {
+		let mut session = session();
+		for letter in 0..=254 {
+			session.toggle_candidate(5, letter).unwrap();
+		}
+
+		assert!(matches!(
+			session.toggle_candidate(5, 255),
+			Err(SolveError::TooManyCandidates(5))
+		));
+		session.toggle_candidate(5, 0).unwrap();
+		session.toggle_candidate(5, 255).unwrap();
+		assert!(session.history().to_bytes().is_ok());
+
+		let mut puz = session.into_puz();
+		puz.annotations.as_mut().unwrap()[5].candidates.push(0);
+		assert!(matches!(
+			puz.to_bytes(),
+			Err(WritePuzError::TooManyCandidates(256))
+		));
+	}
+
+	#[test]
+	fn it_solves_diagramless_puzzles() {
+		let mut puz = session().into_puz();
+		puz.puzzle_type = PuzzleType::Diagramless;
+		puz.solution = Grid::from_bytes(3, 3, b"SUNE:OART");
+		puz.player_state = Grid::from_bytes(3, 3, b"S--------");
+		puz.rebus = None;
+		puz.user_rebus = None;
+		let mut session = SolveSession::new(puz);
+
+		assert_eq!(session.numbering().across().count(), 3);
+
+		session.toggle_block(4).unwrap();
+		session.toggle_block(8).unwrap();
+		assert_eq!(session.puz().player_state.to_bytes(), b"S---:---:");
+		assert_eq!(session.numbering().across().count(), 2);
+		assert_eq!(
+			session.squares(Scope::Word(6, Direction::Across)),
+			Ok(vec![6, 7])
+		);
+
+		assert_eq!(session.check(Scope::Puzzle), Ok(vec![8]));
+
+		session.toggle_block(8).unwrap();
+		session.reveal(Scope::Puzzle).unwrap();
+		assert_eq!(session.puz().player_state.to_bytes(), b"SUNE:OART");
+		assert_eq!(session.is_solved(), Ok(true));
+		assert_eq!(session.numbering(), &session.puz().numbering());
+
+		let bytes = session.into_puz().to_bytes().unwrap();
+		let reparsed = parse_a_puz(&bytes).expect("Parsing Failed");
+		assert_eq!(reparsed.player_state.cells[4], Cell::DiagramlessBlock);
+
+		let mut normal = self::session();
+		assert_eq!(normal.toggle_block(4), Err(SolveError::NotDiagramless));
+	}
+
+	#[test]
+	fn it_requires_a_solution() {
+		let puzzle = include_bytes!("../fixtures/test-no-solution.puz");
+		let mut session = SolveSession::new(parse_a_puz(puzzle).expect("Parsing Failed"));
+
+		assert_eq!(
+			session.check(Scope::Puzzle),
+			Err(SolveError::MissingSolution)
+		);
+		assert_eq!(
+			session.reveal(Scope::Square(0)),
+			Err(SolveError::MissingSolution)
+		);
+		assert_eq!(session.clear(Scope::Puzzle), Ok(()));
+
+		let puzzle = include_bytes!("../fixtures/test-extensions.puz");
+		let mut puz = parse_a_puz(puzzle).expect("Parsing Failed");
+		puz.scramble(1234).unwrap();
+		let mut session = SolveSession::new(puz);
+
+		assert_eq!(
+			session.check(Scope::Puzzle),
+			Err(SolveError::ScrambledSolution)
+		);
+	}
+}
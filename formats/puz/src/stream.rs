@@ -0,0 +1,345 @@
+use crate::{
+	extensions, parse_a_puz, parse_a_puz_with_diagnostics, Diagnostic, ParseError, ParsePuzError,
+	PuzFile, FILE_MAGIC,
+};
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// Longest string (title, clue, notes, ...) accepted from a stream
+pub const MAX_STRING_LENGTH: usize = 0x10000;
+
+/// Longest total of all strings accepted from a stream. Without it, the
+/// clue count would allow about 4 GiB of strings.
+pub const MAX_STRINGS_LENGTH: usize = 0x100000;
+
+/// Longest total of all extension sections, including their headers,
+/// accepted from a stream. Sections can follow each other without end.
+pub const MAX_SECTIONS_LENGTH: usize = 0x100000;
+
+/// Most bytes after the puzzle kept as trailing data by `read_puz`
+pub const MAX_TRAILING_LENGTH: usize = 0x10000;
+
+/// Most bytes skipped in a stream while searching for the start of a puzzle
+pub const MAX_PREAMBLE_LENGTH: usize = 0x10000;
+
+/// Length of the header up to and including the board configuration
+const HEADER_LENGTH: usize = 0x34;
+
+/// Offset of the width in the header, followed by the height and clue count
+const BOARD_CONFIGURATION_OFFSET: usize = 0x2C;
+
+/// Reads a single byte, or nothing at the end of the stream
+fn read_byte(reader: &mut impl Read) -> io::Result<Option<u8>> {
+	let mut byte = [0];
+	loop {
+		match reader.read(&mut byte) {
+			Ok(0) => return Ok(None),
+			Ok(_) => return Ok(Some(byte[0])),
+			Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+			Err(error) => return Err(error),
+		}
+	}
+}
+
+/// Reads up to `length` bytes into `bytes`, fewer only at the end of the
+/// stream. Returns the number of bytes read.
+fn read_up_to(reader: &mut impl Read, bytes: &mut Vec<u8>, length: usize) -> io::Result<usize> {
+	reader.take(length as u64).read_to_end(bytes)
+}
+
+/// The bytes of a puzzle read from a stream
+pub(crate) struct PuzBytes {
+	/// Preamble and puzzle, up to the end of the last extension section
+	pub puzzle: Vec<u8>,
+	/// Bytes read after the puzzle that turned out not to be a section
+	pub over_read: Vec<u8>,
+}
+
+/// Collects the bytes of the next puzzle in the stream, see `collect_puz_bytes`
+pub(crate) fn read_puz_bytes(reader: &mut impl Read) -> Result<PuzBytes, ParseError> {
+	let mut bytes = Vec::new();
+	let mut puz_start = None;
+	let mut field = "file magic";
+
+	match collect_puz_bytes(reader, &mut bytes, &mut puz_start, &mut field) {
+		Ok(over_read) => Ok(PuzBytes {
+			puzzle: bytes,
+			over_read,
+		}),
+		Err(kind) => Err(ParseError {
+			kind,
+			field,
+			offset: bytes.len(),
+			puz_start,
+		}),
+	}
+}
+
+/// Reads the next puzzle into `bytes`. Only the preamble, the header, the
+/// grids of the declared size, the declared number of strings and the
+/// extension sections are read. Returns the bytes read after the puzzle.
+fn collect_puz_bytes(
+	reader: &mut impl Read,
+	bytes: &mut Vec<u8>,
+	puz_start: &mut Option<usize>,
+	field: &mut &'static str,
+) -> Result<Vec<u8>, ParsePuzError> {
+	// The magic starts two bytes into the puzzle, after the file checksum
+	while bytes.len() < FILE_MAGIC.len() + 2 || !bytes.ends_with(FILE_MAGIC) {
+		if bytes.len() == MAX_PREAMBLE_LENGTH + FILE_MAGIC.len() + 2 {
+			return Err(ParsePuzError::PreambleTooLong {
+				limit: MAX_PREAMBLE_LENGTH,
+			});
+		}
+		bytes.push(read_byte(reader)?.ok_or(ParsePuzError::NotAPuz)?);
+	}
+	let start = bytes.len() - FILE_MAGIC.len() - 2;
+	*puz_start = Some(start);
+
+	*field = "header";
+	let header_rest = HEADER_LENGTH - (bytes.len() - start);
+	let found = read_up_to(reader, bytes, header_rest)?;
+	if found < header_rest {
+		return Err(ParsePuzError::UnexpectedEnd {
+			expected: header_rest,
+			found,
+		});
+	}
+
+	let board_configuration = &bytes[(start + BOARD_CONFIGURATION_OFFSET)..];
+	let board_size = board_configuration[0] as usize * board_configuration[1] as usize;
+	let clue_count = u16::from_le_bytes([board_configuration[2], board_configuration[3]]);
+
+	// The grids can be checked by the parser, the strings are needed to find
+	// the sections
+	*field = "grids";
+	read_up_to(reader, bytes, 2 * board_size)?;
+
+	*field = "strings";
+	let strings_start = bytes.len();
+	for index in 0..(clue_count as usize + 4) {
+		let mut length = 0;
+		loop {
+			let Some(byte) = read_byte(reader)? else {
+				return Ok(Vec::new());
+			};
+			bytes.push(byte);
+			if byte == 0 {
+				break;
+			}
+
+			length += 1;
+			if length > MAX_STRING_LENGTH {
+				return Err(ParsePuzError::StringTooLong {
+					index,
+					limit: MAX_STRING_LENGTH,
+				});
+			}
+			if bytes.len() - strings_start > MAX_STRINGS_LENGTH {
+				return Err(ParsePuzError::StringsTooLong {
+					limit: MAX_STRINGS_LENGTH,
+				});
+			}
+		}
+	}
+
+	*field = "extension section";
+	let sections_start = bytes.len();
+	loop {
+		let mut header = Vec::with_capacity(8);
+		read_up_to(reader, &mut header, 8)?;
+		if !extensions::is_section_header(&header) {
+			return Ok(header);
+		}
+
+		let length = u16::from_le_bytes([header[4], header[5]]) as usize;
+		if bytes.len() - sections_start + 8 + length + 1 > MAX_SECTIONS_LENGTH {
+			return Err(ParsePuzError::SectionsTooLong {
+				limit: MAX_SECTIONS_LENGTH,
+			});
+		}
+		bytes.extend(header);
+		read_up_to(reader, bytes, length + 1)?;
+	}
+}
+
+/// Reads a puzzle from a stream, up to the end of the stream.
+///
+/// The preamble is searched for byte by byte, so slow readers like files
+/// should be wrapped in a `BufReader`. The puzzle ends with the last
+/// extension section, and like with `parse_a_puz`, everything after it is
+/// kept as trailing data. Use `read_puz_seek` to stop after the puzzle.
+///
+/// To bound the memory used for untrusted streams, at most
+/// `MAX_PREAMBLE_LENGTH` bytes are searched for the puzzle, and strings
+/// are limited to `MAX_STRING_LENGTH` bytes each and `MAX_STRINGS_LENGTH`
+/// bytes in total. The extension sections are limited to
+/// `MAX_SECTIONS_LENGTH` bytes in total, the trailing data to
+/// `MAX_TRAILING_LENGTH` bytes.
+pub fn read_puz<R: Read>(reader: R) -> Result<PuzFile, ParseError> {
+	read_puz_with_diagnostics(reader).map(|(puz, _)| puz)
+}
+
+/// Like `read_puz`, but also returns the problems that could be recovered
+/// from while parsing.
+pub fn read_puz_with_diagnostics<R: Read>(
+	mut reader: R,
+) -> Result<(PuzFile, Vec<Diagnostic>), ParseError> {
+	let PuzBytes {
+		mut puzzle,
+		over_read,
+	} = read_puz_bytes(&mut reader)?;
+
+	let trailing_start = puzzle.len();
+	puzzle.extend(over_read);
+	let rest = MAX_TRAILING_LENGTH + 1 - (puzzle.len() - trailing_start);
+	let trailing_error = |kind| ParseError {
+		kind,
+		field: "trailing data",
+		offset: trailing_start,
+		puz_start: None,
+	};
+	read_up_to(&mut reader, &mut puzzle, rest).map_err(|error| trailing_error(error.into()))?;
+	if puzzle.len() - trailing_start > MAX_TRAILING_LENGTH {
+		return Err(trailing_error(ParsePuzError::TrailingTooLong {
+			limit: MAX_TRAILING_LENGTH,
+		}));
+	}
+
+	parse_a_puz_with_diagnostics(&puzzle)
+}
+
+/// Reads a puzzle from a seekable stream and leaves the stream right after
+/// the puzzle, e.g. at the start of the next one.
+pub fn read_puz_seek<R: Read + Seek>(reader: &mut R) -> Result<PuzFile, ParseError> {
+	let bytes = read_puz_bytes(reader)?;
+	reader
+		.seek(SeekFrom::Current(-(bytes.over_read.len() as i64)))
+		.map_err(|error| ParseError {
+			kind: error.into(),
+			field: "extension section",
+			offset: bytes.puzzle.len(),
+			puz_start: None,
+		})?;
+
+	parse_a_puz(&bytes.puzzle)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn it_reads_streams() {
+		let fixtures: [&[u8]; 2] = [
+			include_bytes!("../fixtures/test-no-solution.puz"),
+			include_bytes!("../fixtures/test-extensions.puz"),
+		];
+
+		for fixture in fixtures {
+			let expected = parse_a_puz(fixture).expect("Parsing Failed");
+			assert_eq!(read_puz(fixture).expect("Reading Failed"), expected);
+		}
+	}
+
+	#[test]
+	fn it_keeps_trailing_data() {
+		let puzzle = include_bytes!("../fixtures/test-extensions.puz");
+		let mut bytes = puzzle.to_vec();
+		bytes.extend(b"\0\0trailing garbage that is long");
+
+		let puz = read_puz(&bytes[..]).expect("Reading Failed");
+		assert_eq!(puz, parse_a_puz(&bytes).unwrap());
+		assert_eq!(puz.to_bytes().unwrap(), bytes);
+
+		bytes.extend(vec![0; MAX_TRAILING_LENGTH]);
+		assert!(matches!(
+			read_puz(&bytes[..]),
+			Err(ParseError {
+				kind: ParsePuzError::TrailingTooLong { .. },
+				field: "trailing data",
+				..
+			})
+		));
+	}
+
+	#[test]
+	fn it_stops_after_the_puzzle() {
+		let first = include_bytes!("../fixtures/test-extensions.puz");
+		let second = include_bytes!("../fixtures/test-no-solution.puz");
+		let mut bytes = first.to_vec();
+		bytes.extend(second);
+
+		let mut reader = io::Cursor::new(&bytes);
+		let puz = read_puz_seek(&mut reader).expect("Reading Failed");
+		assert_eq!(puz, parse_a_puz(first).unwrap());
+		assert_eq!(reader.position(), first.len() as u64);
+
+		let puz = read_puz_seek(&mut reader).expect("Reading Failed");
+		assert_eq!(puz, parse_a_puz(second).unwrap());
+		assert!(matches!(
+			read_puz_seek(&mut reader),
+			Err(ParseError {
+				kind: ParsePuzError::NotAPuz,
+				..
+			})
+		));
+	}
+
+	#[test]
+	fn it_bounds_the_memory_used() {
+		let puzzle = include_bytes!("../fixtures/test-no-solution.puz");
+
+		let mut bytes = vec![0; MAX_PREAMBLE_LENGTH];
+		bytes.extend(puzzle);
+		let puz = read_puz(&bytes[..]).expect("Reading Failed");
+		assert_eq!(
+			puz.garbage.preamble.map(|preamble| preamble.len()),
+			Some(MAX_PREAMBLE_LENGTH)
+		);
+
+		bytes.insert(0, 0);
+		assert!(matches!(
+			read_puz(&bytes[..]),
+			Err(ParseError {
+				kind: ParsePuzError::PreambleTooLong { .. },
+				..
+			})
+		));
+
+		// As many clues as possible, each as long as possible
+		let mut bytes = puzzle[..(HEADER_LENGTH + 18)].to_vec();
+		bytes[(BOARD_CONFIGURATION_OFFSET + 2)..(BOARD_CONFIGURATION_OFFSET + 4)]
+			.copy_from_slice(&[0xff, 0xff]);
+		for _ in 0..20 {
+			bytes.extend(vec![b'A'; MAX_STRING_LENGTH - 1]);
+			bytes.push(0);
+		}
+		assert!(matches!(
+			read_puz(&bytes[..]),
+			Err(ParseError {
+				kind: ParsePuzError::StringsTooLong { .. },
+				field: "strings",
+				..
+			})
+		));
+
+		// Sections without end
+		let mut bytes = puzzle.to_vec();
+		let data = vec![b'A'; 0xfffe];
+		for _ in 0..20 {
+			bytes.extend(b"XTRA");
+			bytes.extend((data.len() as u16).to_le_bytes());
+			bytes.extend([0, 0]);
+			bytes.extend(&data);
+			bytes.push(0);
+		}
+		assert!(matches!(
+			read_puz(&bytes[..]),
+			Err(ParseError {
+				kind: ParsePuzError::SectionsTooLong { .. },
+				field: "extension section",
+				..
+			})
+		));
+	}
+}
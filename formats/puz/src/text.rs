@@ -0,0 +1,124 @@
+use crate::{ParsePuzError, PuzVersion, WritePuzError};
+use alloc::{borrow::ToOwned, string::String, vec::Vec};
+
+/// Characters for the bytes 0x80 - 0x9F in Windows-1252.
+/// The 5 unassigned bytes map to the latin-1 control characters of the same
+/// value, so every byte can be decoded and encoded again without loss.
+const WINDOWS_1252_HIGH: [char; 32] = [
+	'€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8d}', 'Ž', '\u{8f}',
+	'\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9d}', 'ž', 'Ÿ',
+];
+
+/// Encoding used for the strings of a puz file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextEncoding {
+	/// Used by versions 1.x. Officially ISO-8859-1, but in practice files
+	/// are written with the Windows-1252 superset.
+	Windows1252,
+	/// Used from version 2.0 on
+	Utf8,
+}
+
+impl PuzVersion {
+	pub fn text_encoding(&self) -> TextEncoding {
+		if self.major >= 2 {
+			TextEncoding::Utf8
+		} else {
+			TextEncoding::Windows1252
+		}
+	}
+}
+
+impl TextEncoding {
+	pub fn decode(&self, bytes: &[u8]) -> Result<String, ParsePuzError> {
+		match self {
+			Self::Windows1252 => Ok(bytes
+				.iter()
+				.map(|&byte| match byte {
+					0x80..=0x9f => WINDOWS_1252_HIGH[(byte - 0x80) as usize],
+					_ => byte as char,
+				})
+				.collect()),
+			Self::Utf8 => Ok(core::str::from_utf8(bytes)?.to_owned()),
+		}
+	}
+
+	/// Encodes a string for a puz file. Strings are NUL-terminated in the
+	/// file, so they can not contain NUL characters themselves.
+	pub fn encode(&self, text: &str) -> Result<Vec<u8>, WritePuzError> {
+		if text.contains('\0') {
+			return Err(WritePuzError::NulInString(text.to_owned()));
+		}
+
+		match self {
+			Self::Windows1252 => text
+				.chars()
+				.map(|character| match character as u32 {
+					code @ (0x00..=0x7f | 0xa0..=0xff) => Ok(code as u8),
+					_ => WINDOWS_1252_HIGH
+						.iter()
+						.position(|&high| high == character)
+						.map(|position| 0x80 + position as u8)
+						.ok_or(WritePuzError::UnencodableCharacter(character)),
+				})
+				.collect(),
+			Self::Utf8 => Ok(text.as_bytes().to_vec()),
+		}
+	}
+}
+
+/// Splits the next `count` NUL-terminated strings off the start of `bytes`.
+/// Returns the strings (without terminator) and the number of bytes consumed,
+/// or the number of strings that could be found if there are not enough.
+pub(crate) fn split_strings(bytes: &[u8], count: usize) -> Result<(Vec<&[u8]>, usize), usize> {
+	let mut strings = Vec::with_capacity(count);
+	let mut consumed = 0;
+
+	while strings.len() < count {
+		let length = bytes[consumed..]
+			.iter()
+			.position(|&byte| byte == 0)
+			.ok_or(strings.len())?;
+
+		strings.push(&bytes[consumed..(consumed + length)]);
+		consumed += length + 1;
+	}
+
+	Ok((strings, consumed))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn it_decodes_windows_1252() {
+		let decoded = TextEncoding::Windows1252
+			.decode(b"Caf\xe9 \x80 \x85 \x81")
+			.unwrap();
+
+		assert_eq!(decoded, "Café € … \u{81}");
+	}
+
+	#[test]
+	fn it_encodes_windows_1252() {
+		let text = "Café € … \u{81}";
+
+		let encoded = TextEncoding::Windows1252.encode(text).unwrap();
+
+		assert_eq!(encoded, b"Caf\xe9 \x80 \x85 \x81");
+		assert!(matches!(
+			TextEncoding::Windows1252.encode("🐈"),
+			Err(WritePuzError::UnencodableCharacter('🐈'))
+		));
+	}
+
+	#[test]
+	fn it_splits_strings() {
+		let (strings, consumed) = split_strings(b"one\0\0three\0rest", 3).unwrap();
+
+		assert_eq!(strings, [&b"one"[..], b"", b"three"]);
+		assert_eq!(consumed, 11);
+		assert_eq!(split_strings(b"one\0two", 3), Err(1));
+	}
+}
@@ -0,0 +1,494 @@
+use crate::checksum::{checksum_region, EncodedStrings, HeaderChecksums};
+use crate::extensions::{self, KNOWN_SECTIONS};
+use crate::{Grid, PuzFile, PuzVersion, Rebus, Timer, UnknownSection, FILE_MAGIC};
+use alloc::{format, string::String, vec::Vec};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum WritePuzError {
+	#[error("the {name} grid is {found_width}x{found_height}, but the puzzle is {width}x{height}")]
+	GridSizeMismatch {
+		name: &'static str,
+		width: u8,
+		height: u8,
+		found_width: u8,
+		found_height: u8,
+	},
+	#[error("the {name} grid has {found} cells, but should have {expected}")]
+	GridCellCountMismatch {
+		name: &'static str,
+		expected: usize,
+		found: usize,
+	},
+	#[error("the clue count is {clue_count}, but there are {clues} clues")]
+	ClueCountMismatch { clue_count: u16, clues: usize },
+	#[error(
+		"extension section {} should be {expected} entries long, but is {found} entries long",
+		String::from_utf8_lossy(.name)
+	)]
+	SectionSizeMismatch {
+		name: [u8; 4],
+		expected: usize,
+		found: usize,
+	},
+	#[error(
+		"extension section {} is {length} bytes long, which does not fit into a puz file",
+		String::from_utf8_lossy(.name)
+	)]
+	SectionTooLong { name: [u8; 4], length: usize },
+	#[error("character '{0}' can not be encoded in the text encoding of this puz version")]
+	UnencodableCharacter(char),
+	#[error("strings in puz files can not contain NUL characters: '{0}'")]
+	NulInString(String),
+	#[error("a square has {0} candidates, but at most 255 can be stored")]
+	TooManyCandidates(usize),
+	#[error(
+		"version {0:?} can not be written, only single digits and a single byte extension fit"
+	)]
+	UnwritableVersion(PuzVersion),
+	#[cfg(feature = "std")]
+	#[error("could not write puz data")]
+	Io(#[from] std::io::Error),
+}
+
+impl TryFrom<&PuzVersion> for [u8; 4] {
+	type Error = WritePuzError;
+
+	/// Only single digit version numbers and single byte extensions fit
+	fn try_from(version: &PuzVersion) -> Result<Self, Self::Error> {
+		let extension = version
+			.extension
+			.map_or(Some(0), |extension| u8::try_from(extension).ok());
+		match extension {
+			Some(extension) if version.major <= 9 && version.minor <= 9 => Ok([
+				b'0' + version.major, // to ascii
+				b'.',
+				b'0' + version.minor, // to ascii
+				extension,
+			]),
+			_ => Err(WritePuzError::UnwritableVersion(*version)),
+		}
+	}
+}
+
+fn check_grid(puz: &PuzFile, grid: &Grid, name: &'static str) -> Result<(), WritePuzError> {
+	if grid.width != puz.width || grid.height != puz.height {
+		return Err(WritePuzError::GridSizeMismatch {
+			name,
+			width: puz.width,
+			height: puz.height,
+			found_width: grid.width,
+			found_height: grid.height,
+		});
+	}
+
+	let expected = puz.width as usize * puz.height as usize;
+	if grid.cells.len() != expected {
+		return Err(WritePuzError::GridCellCountMismatch {
+			name,
+			expected,
+			found: grid.cells.len(),
+		});
+	}
+
+	Ok(())
+}
+
+fn check_section_size(name: &[u8; 4], expected: usize, found: usize) -> Result<(), WritePuzError> {
+	if expected == found {
+		Ok(())
+	} else {
+		Err(WritePuzError::SectionSizeMismatch {
+			name: *name,
+			expected,
+			found,
+		})
+	}
+}
+
+/// Encodes the data of the known extension section with the given name, if
+/// the puzzle has it
+fn encode_known_section(puz: &PuzFile, name: &[u8; 4]) -> Result<Option<Vec<u8>>, WritePuzError> {
+	let board_size = puz.width as usize * puz.height as usize;
+	let encoding = puz.version.text_encoding();
+
+	Ok(match name {
+		b"GRBS" => match &puz.rebus {
+			Some(rebus) => {
+				check_section_size(name, board_size, rebus.board.len())?;
+				Some(rebus.board.clone())
+			}
+			None => None,
+		},
+		b"RTBL" => match &puz.rebus {
+			Some(rebus) => {
+				let table: String = rebus
+					.table
+					.iter()
+					.map(|entry| format!("{:>2}:{};", entry.key, entry.solution))
+					.collect();
+				Some(encoding.encode(&table)?)
+			}
+			None => None,
+		},
+		b"LTIM" => puz.timer.map(|timer| {
+			let stopped = if timer.running { 0 } else { 1 };
+			format!("{},{}", timer.elapsed_seconds, stopped).into_bytes()
+		}),
+		b"GEXT" => match &puz.cell_flags {
+			Some(flags) => {
+				check_section_size(name, board_size, flags.len())?;
+				Some(flags.iter().map(|flags| flags.bits()).collect())
+			}
+			None => None,
+		},
+		b"RUSR" => match &puz.user_rebus {
+			Some(entries) => {
+				check_section_size(name, board_size, entries.len())?;
+				let mut data = Vec::new();
+				for entry in entries {
+					if let Some(entry) = entry {
+						data.extend(encoding.encode(entry)?);
+					}
+					data.push(0);
+				}
+				Some(data)
+			}
+			None => None,
+		},
+		b"ANNO" => match &puz.annotations {
+			Some(annotations) => {
+				check_section_size(name, board_size, annotations.len())?;
+				Some(extensions::encode_annotations(annotations)?)
+			}
+			None => None,
+		},
+		_ => None,
+	})
+}
+
+/// Name, data and checksum of an extension section
+type EncodedSection = ([u8; 4], Vec<u8>, u16);
+
+/// The LTIM or RTBL section as it was read, if it still holds the current
+/// timer or rebus table
+fn unchanged_text_section<'a>(puz: &'a PuzFile, name: &[u8; 4]) -> Option<&'a UnknownSection> {
+	let section = puz
+		.garbage
+		.text_sections
+		.iter()
+		.find(|section| &section.name == name)?;
+	let unchanged = match name {
+		b"LTIM" => puz.timer.is_some() && Timer::from_section(&section.data) == puz.timer,
+		b"RTBL" => puz.rebus.as_ref().is_some_and(|rebus| {
+			let encoding = puz.version.text_encoding();
+			Rebus::from_sections(&rebus.board, &section.data, encoding)
+				.is_ok_and(|read| read == *rebus)
+		}),
+		_ => false,
+	};
+	unchanged.then_some(section)
+}
+
+/// Collects the extension sections in the order they should be written:
+/// the order of the original file first, then sections that were added since.
+fn ordered_sections(puz: &PuzFile) -> Result<Vec<EncodedSection>, WritePuzError> {
+	let mut sections = Vec::new();
+	let mut written_known = Vec::new();
+	let mut unknown_sections: Vec<_> = puz.garbage.unknown_sections.iter().map(Some).collect();
+
+	let known_names = KNOWN_SECTIONS.iter().map(|&&name| name);
+	let unknown_names = puz
+		.garbage
+		.unknown_sections
+		.iter()
+		.map(|section| section.name);
+
+	for name in puz
+		.garbage
+		.section_order
+		.iter()
+		.copied()
+		.chain(known_names)
+		.chain(unknown_names)
+	{
+		if !written_known.contains(&name) {
+			if let Some(section) = unchanged_text_section(puz, &name) {
+				written_known.push(name);
+				sections.push((name, section.data.clone(), section.checksum.into()));
+				continue;
+			}
+			if let Some(data) = encode_known_section(puz, &name)? {
+				written_known.push(name);
+				let checksum = checksum_region(&data, 0);
+				sections.push((name, data, checksum));
+				continue;
+			}
+		}
+
+		let unknown = unknown_sections
+			.iter_mut()
+			.find(|section| section.is_some_and(|section| section.name == name))
+			.and_then(Option::take);
+		if let Some(unknown) = unknown {
+			sections.push((name, unknown.data.clone(), unknown.checksum.into()));
+		}
+	}
+
+	Ok(sections)
+}
+
+impl PuzFile {
+	/// Serializes the puzzle into the bytes of a puz file.
+	///
+	/// All checksums are calculated from the data, the checksum fields of the
+	/// `PuzFile` are not used, but unknown sections are written with their
+	/// stored checksum, and so are LTIM and RTBL sections that still hold
+	/// the timer and rebus table they were read with. The garbage data is
+	/// written back where it was found.
+	pub fn to_bytes(&self) -> Result<Vec<u8>, WritePuzError> {
+		let version = <[u8; 4]>::try_from(&self.version)?;
+		check_grid(self, &self.solution, "solution")?;
+		check_grid(self, &self.player_state, "player state")?;
+
+		if self.clues.len() != self.clue_count as usize {
+			return Err(WritePuzError::ClueCountMismatch {
+				clue_count: self.clue_count,
+				clues: self.clues.len(),
+			});
+		}
+
+		let encoding = self.version.text_encoding();
+		let title = encoding.encode(&self.title)?;
+		let author = encoding.encode(&self.author)?;
+		let copyright = encoding.encode(&self.copyright)?;
+		let clues = self
+			.clues
+			.iter()
+			.map(|clue| encoding.encode(clue))
+			.collect::<Result<Vec<_>, _>>()?;
+		let notes = encoding.encode(&self.notes)?;
+
+		let strings = EncodedStrings {
+			title: &title,
+			author: &author,
+			copyright: &copyright,
+			clues: clues.iter().map(Vec::as_slice).collect(),
+			notes: &notes,
+		};
+
+		let mut board_configuration = Vec::with_capacity(8);
+		board_configuration.push(self.width);
+		board_configuration.push(self.height);
+		board_configuration.extend(self.clue_count.to_le_bytes());
+		board_configuration.extend(u16::from(self.puzzle_type).to_le_bytes());
+		board_configuration.extend(u16::from(self.solution_type).to_le_bytes());
+
+		let solution = self.solution.to_bytes();
+		let player_state = self.player_state.to_bytes();
+
+		let checksums = HeaderChecksums::calculate(
+			&board_configuration,
+			&solution,
+			&player_state,
+			&strings,
+			self.version.includes_notes_in_checksums(),
+		);
+
+		let mut bytes = Vec::new();
+
+		if let Some(preamble) = &self.garbage.preamble {
+			bytes.extend(preamble);
+		}
+
+		bytes.extend(u16::from(checksums.file).to_le_bytes());
+		bytes.extend(FILE_MAGIC);
+		bytes.extend(u16::from(checksums.board_configuration).to_le_bytes());
+		bytes.extend(checksums.masked.to_masked_bytes());
+		bytes.extend(version);
+		bytes.extend(self.garbage.unknown_header_data_1);
+		bytes.extend(self.checksum_scrambled.map_or(0, u16::from).to_le_bytes());
+		bytes.extend(self.garbage.unknown_header_data_2);
+		bytes.extend(board_configuration);
+		bytes.extend(solution);
+		bytes.extend(player_state);
+
+		for string in [&title, &author, &copyright]
+			.into_iter()
+			.chain(&clues)
+			.chain([&notes])
+		{
+			bytes.extend(string);
+			bytes.push(0);
+		}
+
+		for (name, data, checksum) in ordered_sections(self)? {
+			let length = u16::try_from(data.len()).map_err(|_| WritePuzError::SectionTooLong {
+				name,
+				length: data.len(),
+			})?;
+
+			bytes.extend(name);
+			bytes.extend(length.to_le_bytes());
+			bytes.extend(checksum.to_le_bytes());
+			bytes.extend(data);
+			bytes.push(0);
+		}
+
+		if let Some(trailing) = &self.garbage.trailing {
+			bytes.extend(trailing);
+		}
+
+		Ok(bytes)
+	}
+}
+
+/// Writes the puzzle as a puz file, see `PuzFile::to_bytes`
+#[cfg(feature = "std")]
+pub fn write_puz<W: std::io::Write>(puz: &PuzFile, mut writer: W) -> Result<(), WritePuzError> {
+	writer.write_all(&puz.to_bytes()?)?;
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{parse_a_puz, parse_a_puz_with_mode, CellFlags, Crc16Checksum, ParseMode, Timer};
+
+	#[test]
+	fn it_writes_identical_bytes() {
+		let fixtures: [&[u8]; 2] = [
+			include_bytes!("../fixtures/test-no-solution.puz"),
+			include_bytes!("../fixtures/test-extensions.puz"),
+		];
+
+		for fixture in fixtures {
+			let parsed = parse_a_puz(fixture).expect("Parsing Failed");
+
+			assert_eq!(parsed.to_bytes().expect("Writing Failed"), fixture);
+		}
+	}
+
+	#[test]
+	fn it_writes_stored_checksums_of_unknown_sections() {
+		let puzzle = include_bytes!("../fixtures/test-extensions.puz");
+		let mut corrupted = puzzle.to_vec();
+		let hello_position = puzzle.windows(5).position(|bytes| bytes == b"hello");
+		corrupted[hello_position.unwrap()] = b'j';
+
+		let (parsed, _) =
+			parse_a_puz_with_mode(&corrupted, ParseMode::Lenient).expect("Parsing Failed");
+
+		assert_eq!(parsed.garbage.unknown_sections[0].data, b"jello, world");
+		assert_eq!(parsed.to_bytes().expect("Writing Failed"), corrupted);
+	}
+
+	#[test]
+	fn it_keeps_the_formatting_of_text_sections() {
+		let puzzle = include_bytes!("../fixtures/test-extensions.puz");
+		let mut parsed = parse_a_puz(puzzle).expect("Parsing Failed");
+		let timer = parsed
+			.garbage
+			.text_sections
+			.iter_mut()
+			.find(|section| &section.name == b"LTIM")
+			.unwrap();
+		timer.data = b"42, 1".to_vec();
+		timer.checksum = Crc16Checksum::of(b"42, 1");
+
+		let bytes = parsed.to_bytes().unwrap();
+		let mut reparsed = parse_a_puz(&bytes).expect("Parsing Failed");
+
+		assert!(bytes.windows(5).any(|bytes| bytes == b"42, 1"));
+		assert_eq!(reparsed.timer, parsed.timer);
+		assert_eq!(reparsed.to_bytes().unwrap(), bytes);
+
+		reparsed.timer = Some(Timer {
+			elapsed_seconds: 43,
+			running: false,
+		});
+		let bytes = reparsed.to_bytes().unwrap();
+		assert!(bytes.windows(4).any(|bytes| bytes == b"43,1"));
+	}
+
+	#[test]
+	fn it_writes_edited_puzzles() {
+		let puzzle = include_bytes!("../fixtures/test-extensions.puz");
+		let mut parsed = parse_a_puz(puzzle).expect("Parsing Failed");
+
+		parsed.title = "Œuvres complètes".to_owned();
+		parsed.clues[2] = "Ne … pas".to_owned();
+		parsed.timer = Some(Timer {
+			elapsed_seconds: 1234,
+			running: true,
+		});
+		parsed.garbage.trailing = Some(b"bye".to_vec());
+
+		let reparsed = parse_a_puz(&parsed.to_bytes().unwrap()).expect("Parsing Failed");
+
+		assert_eq!(reparsed.title, parsed.title);
+		assert_eq!(reparsed.clues, parsed.clues);
+		assert_eq!(reparsed.timer, parsed.timer);
+		assert_eq!(reparsed.garbage.section_order, parsed.garbage.section_order);
+		assert_eq!(reparsed.garbage.trailing, parsed.garbage.trailing);
+	}
+
+	#[test]
+	fn it_appends_new_sections() {
+		let puzzle = include_bytes!("../fixtures/test-no-solution.puz");
+		let mut parsed = parse_a_puz(puzzle).expect("Parsing Failed");
+
+		let mut flags = vec![CellFlags::empty(); 9];
+		flags[4] = CellFlags::CIRCLED;
+		parsed.cell_flags = Some(flags.clone());
+
+		let reparsed = parse_a_puz(&parsed.to_bytes().unwrap()).expect("Parsing Failed");
+
+		assert_eq!(reparsed.cell_flags, Some(flags));
+		assert_eq!(reparsed.garbage.section_order, [*b"GEXT"]);
+	}
+
+	#[test]
+	fn it_refuses_unwritable_versions() {
+		let puzzle = include_bytes!("../fixtures/test-no-solution.puz");
+		let mut parsed = parse_a_puz(puzzle).expect("Parsing Failed");
+
+		parsed.version.minor = 10;
+		assert!(matches!(
+			parsed.to_bytes(),
+			Err(WritePuzError::UnwritableVersion(PuzVersion {
+				minor: 10,
+				..
+			}))
+		));
+
+		parsed.version.minor = 3;
+		parsed.version.extension = Some('Œ');
+		assert!(matches!(
+			parsed.to_bytes(),
+			Err(WritePuzError::UnwritableVersion(_))
+		));
+	}
+
+	#[test]
+	fn it_refuses_inconsistent_puzzles() {
+		let puzzle = include_bytes!("../fixtures/test-extensions.puz");
+		let mut parsed = parse_a_puz(puzzle).expect("Parsing Failed");
+
+		parsed.clues.pop();
+		assert!(matches!(
+			parsed.to_bytes(),
+			Err(WritePuzError::ClueCountMismatch {
+				clue_count: 4,
+				clues: 3
+			})
+		));
+
+		parsed.clue_count = 3;
+		parsed.title = "🐈".to_owned();
+		assert!(matches!(
+			parsed.to_bytes(),
+			Err(WritePuzError::UnencodableCharacter('🐈'))
+		));
+	}
+}